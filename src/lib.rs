@@ -25,10 +25,18 @@
 //! to stdout.
 //!
 //! # Static vs. dynamic linking
-//! ## Linux and Mac
-//! At this time, vcpkg has a single triplet on macOS and Linux, which builds
-//! static link versions of libraries. This triplet works well with Rust. It is also possible
+//! ## Linux, Mac and other platforms
+//! The vcpkg triplet is chosen from the architecture and operating system of the Rust target,
+//! so `x86_64-unknown-linux-gnu` uses `x64-linux`, `aarch64-apple-darwin` uses `arm64-osx`,
+//! `aarch64-linux-android` uses `arm64-android` and `aarch64-apple-ios-sim` uses
+//! `arm64-ios-simulator`. These triplets build static link versions of libraries, which
+//! works well with Rust. It is also possible
 //! to select a custom triplet using the `VCPKGRS_TRIPLET` environment variable.
+//!
+//! Dynamic linking on Linux and macOS requires a custom triplet such as `x64-linux-dynamic`
+//! or `arm64-osx-dynamic` selected with `VCPKGRS_TRIPLET`, as well as `VCPKGRS_DYNAMIC`.
+//! The shared libraries are linked with `rustc-link-lib=dylib=`, and the directories that need to
+//! be on the runtime search path are returned in `Library::rpaths`.
 //! ## Windows
 //! On Windows there are three
 //! configurations that are supported for 64-bit builds and another three for 32-bit.
@@ -46,6 +54,39 @@
 //! generate dynamically linked binaries, in which case you will have to arrange for
 //! dlls from your Vcpkg installation to be available in your path.
 //!
+//! The DLLs are copied to `OUT_DIR`. `Config::copy_dlls_to_target_dir` also copies
+//! them to `target/<profile>` and `target/<profile>/deps` so that `cargo run` and
+//! `cargo test` work. Other destinations can be added with `Config::dll_destination`.
+//!
+//! Triplets can also override the linkage of individual ports, for example with
+//! `if(PORT MATCHES "qt5-.*")` in the triplet file, so the linkage of each port is
+//! decided by whether it installed any DLLs. Ports that were built as DLLs are
+//! listed in `Library::dynamic_ports`, and only the DLLs of those ports are copied.
+//!
+//! The import libraries are read to find the DLL that each one refers to, which is
+//! often named differently, e.g. `zlib.lib` and `zlib1.dll`. These are listed in
+//! `Library::lib_dlls`, and only the DLLs that are imported are copied. The import
+//! tables of those DLLs are followed in turn to find any other DLLs in `bin` that they
+//! need. DLLs that can't be found and are not part of Windows are reported with
+//! `cargo:warning` and listed in `Library::unresolved_dlls`.
+//!
+//! Each crate that finds packages records the triplet, installed directory and version
+//! of the ports it found in `vcpkg-rs-resolved-ports.txt` in the cargo target
+//! directory, and a `cargo:warning` is emitted if another crate in the same build
+//! found the same port in a different triplet or installed directory. See
+//! `VCPKGRS_CONSISTENCY` below.
+//!
+//! If the triplet links the C runtime differently to the Rust target, for example a
+//! `-static-md` triplet with `+crt-static`, the link is likely to fail with LNK2038
+//! errors, so a `cargo:warning` is emitted, or `Error::CrtMismatch` is returned if
+//! `Config::crt_mismatch_is_error` is set. The triplet's `VCPKG_CRT_LINKAGE` is used
+//! when the triplet file can be found.
+//!
+//! ## MinGW
+//! Targets using the GNU ABI on Windows, such as `x86_64-pc-windows-gnu`, use the
+//! `x64-mingw-static` community triplet, or `x64-mingw-dynamic` if `VCPKGRS_DYNAMIC` is set.
+//! These produce `libfoo.a` static libraries, or `libfoo.dll.a` import libraries.
+//!
 //! ## WASM 32
 //! 
 //! At this time, vcpkg has a single triplet for wasm32, wasm32-emscripten,
@@ -73,9 +114,31 @@
 //! to adapt [manifest mode of vcpkg](https://learn.microsoft.com/en-us/vcpkg/users/manifests).
 //! If set, this will override the default value of `VCPKG_ROOT/installed`.
 //!  
+//! * `VCPKGRS_OVERLAY_INSTALLED_ROOTS` - Further installed directories, separated like `PATH`,
+//! that are searched for ports before the main installed directory. See
+//! `Config::overlay_installed_root`.
+//!
 //! * `VCPKGRS_TRIPLET` - Use this to override vcpkg-rs' default triplet selection with your own.
 //! This is how to select a custom vcpkg triplet.
 //!
+//! * `VCPKGRS_TRIPLET_CANDIDATES` - A comma separated list of triplets to try in order
+//! if the package is not installed for the selected triplet, for example
+//! `x64-windows-static,x64-windows-custom`. See `Config::triplet_candidates`.
+//!
+//! * `VCPKG_DEFAULT_HOST_TRIPLET` - The triplet that `Config::find_tool` looks in for
+//! executables that run during the build. If it is not set, the host triplet is derived
+//! from the `HOST` target.
+//!
+//! * `VCPKG_OVERLAY_TRIPLETS` - Additional directories to search for triplet files, as used
+//! by `vcpkg install --overlay-triplets`. The triplet file is read to find out whether the
+//! selected triplet links ports statically or dynamically. Anything the file can't be
+//! read for, such as an `include()` of a path built from environment variables, is
+//! decided by the name of the triplet instead, with a `cargo:warning`.
+//!
+//! * `VCPKGRS_CONSISTENCY` - What to do when crates in the same build found a port in
+//! different triplets or installed directories: `warn`, the default, `error` to fail
+//! the build, or `off` to not record the ports that were found at all.
+//!
 //! * `VCPKGRS_NO_FOO` - if set, vcpkg-rs will not attempt to find the
 //! library named `foo`.
 //!
@@ -118,15 +181,18 @@ extern crate lazy_static;
 #[allow(unused_imports)]
 use std::ascii::AsciiExt;
 
+use std::cell::RefCell;
 use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::env;
 use std::error;
 use std::ffi::OsStr;
 use std::fmt;
-use std::fs::{self, File};
-use std::io::{BufRead, BufReader, Read};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 /// Configuration options for finding packages, setting up the tree and emitting metadata to cargo
 #[derive(Default)]
@@ -143,16 +209,125 @@ pub struct Config {
     /// .dlls that must be be found for probing to be considered successful
     required_dlls: Vec<String>,
 
+    /// .dlls installed by the ports find_package resolved, which are only checked
+    /// if the import libraries could not be read
+    port_dlls: Vec<String>,
+
+    /// libraries from lib/manual-link to link as well
+    manual_link_libs: Vec<String>,
+
+    /// linkage of each of the required_libs found by find_package, if it differs
+    /// between ports
+    lib_is_static: HashMap<String, bool>,
+
     /// should DLLs be copied to OUT_DIR?
     copy_dlls: bool,
 
+    /// should the PDBs of the DLLs be copied along with them?
+    copy_pdbs: bool,
+
+    /// should DLLs also be copied to target/<profile> and target/<profile>/deps?
+    copy_dlls_to_target_dir: bool,
+
+    /// other directories to copy DLLs to
+    dll_destinations: Vec<PathBuf>,
+
+    /// copy DLLs, or link to them
+    dll_copy_mode: DllCopyMode,
+
+    /// should a list of the files needed at runtime be written to OUT_DIR?
+    deployment_manifest: bool,
+
+    /// should the copyright files of the ports be copied to OUT_DIR/licenses?
+    copy_licenses: bool,
+
+    /// should every file in the .list files of the ports be checked?
+    verify_installation: bool,
+
+    /// should the ABI hashes of the ports be passed to rustc so that the crate is
+    /// rebuilt when a port is?
+    track_abi: bool,
+
+    /// should the parsed port table be cached in OUT_DIR between build script runs?
+    persistent_cache: bool,
+
     /// override vcpkg installed path, regardless of both VCPKG_ROOT/installed and VCPKG_INSTALLED_ROOT environment variables
     vcpkg_installed_root: Option<PathBuf>,
 
+    /// installed directories searched before the main one, in order
+    overlay_installed_roots: Vec<PathBuf>,
+
     /// override VCPKG_ROOT environment variable
     vcpkg_root: Option<PathBuf>,
 
     target: Option<TargetTriplet>,
+
+    /// triplets to try in order if the port is not installed for the selected triplet
+    triplet_candidates: Vec<TargetTriplet>,
+
+    /// should a C runtime linkage mismatch be an error rather than a warning?
+    crt_mismatch_is_error: bool,
+}
+
+/// How DLLs are put into the directories they are deployed to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DllCopyMode {
+    /// copy the DLL
+    Copy,
+    /// create a hard link to the DLL in the vcpkg tree, or copy it if that fails,
+    /// for example because the vcpkg tree is on a different drive
+    HardLink,
+    /// create a symbolic link to the DLL in the vcpkg tree, or copy it if that fails,
+    /// for example because creating symbolic links needs Developer Mode on Windows
+    Symlink,
+}
+
+impl Default for DllCopyMode {
+    fn default() -> DllCopyMode {
+        DllCopyMode::Copy
+    }
+}
+
+/// Details of a vcpkg port that is linked
+#[derive(Clone, Debug, PartialEq)]
+pub struct PortInfo {
+    /// version of the port, including the port version if the port has been
+    /// revised, e.g. `1.2.11#9`
+    pub version: String,
+
+    /// the ABI hash vcpkg uses to identify this build of the port, if known
+    pub abi: Option<String>,
+
+    /// the ports that this port depends on
+    pub deps: Vec<String>,
+
+    /// SPDX license expression from `share/<port>/vcpkg.spdx.json`, if present
+    pub license: Option<String>,
+
+    /// home page from `share/<port>/vcpkg.spdx.json`, if present
+    pub homepage: Option<String>,
+
+    /// the installed directory the port was found in
+    pub installed_root: PathBuf,
+}
+
+/// A file that the `.list` file of a port says is installed, but that is not
+/// as it should be
+#[derive(Clone, Debug, PartialEq)]
+pub enum FileProblem {
+    /// the file does not exist
+    Missing(PathBuf),
+    /// the file is empty
+    Empty(PathBuf),
+}
+
+impl fmt::Display for FileProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            FileProblem::Missing(ref path) => write!(f, "{} is missing", path.display()),
+            FileProblem::Empty(ref path) => write!(f, "{} is empty", path.display()),
+        }
+    }
 }
 
 /// Details of a package that was found
@@ -173,18 +348,65 @@ pub struct Library {
     /// libraries found are static
     pub is_static: bool,
 
+    /// Paths that binaries need in their runtime search path (rpath) to find the
+    /// shared libraries that were found, on platforms other than Windows
+    pub rpaths: Vec<PathBuf>,
+
     /// DLLs found
     pub found_dlls: Vec<PathBuf>,
 
+    /// program databases for the DLLs, if `Config::copy_pdbs` is set
+    pub found_pdbs: Vec<PathBuf>,
+
+    /// the port that installed each of the `found_dlls`, where it is known, if
+    /// `Config::deployment_manifest` is set
+    pub dll_ports: BTreeMap<PathBuf, String>,
+
     /// static libs or import libs found
     pub found_libs: Vec<PathBuf>,
 
     /// link name of libraries found, this is useful to emit linker commands
     pub found_names: Vec<String>,
 
+    /// The DLL that each of the import libraries in `found_libs` refers to, as read
+    /// from the import library itself. The name of the DLL often differs from the
+    /// name of the import library, e.g. `zlib.lib` imports `zlib1.dll`.
+    pub lib_dlls: BTreeMap<PathBuf, PathBuf>,
+
+    /// DLLs that the found DLLs import, directly or indirectly, that are neither in
+    /// the `bin` directory of the triplet nor part of Windows. The program is not
+    /// likely to start unless these are provided some other way.
+    pub unresolved_dlls: Vec<String>,
+
+    /// The problems found with the installed files of each port, if
+    /// `Config::verify_installation` is set.
+    pub file_problems: BTreeMap<String, Vec<FileProblem>>,
+
     /// ports that are providing the libraries to link to, in port link order
     pub ports: Vec<String>,
 
+    /// Ports that are linked dynamically. This is usually all or none of `ports`, but
+    /// triplets can override the linkage of individual ports.
+    pub dynamic_ports: Vec<String>,
+
+    /// Executables in `tools/` provided by the ports, by name without any `.exe`
+    /// extension. These are built for the vcpkg triplet, so when cross compiling they
+    /// may not run on the build machine; see `Config::find_tool`.
+    pub tools: BTreeMap<String, PathBuf>,
+
+    /// The files in `include/` that belong to each port, for example to generate
+    /// a bindgen allowlist from the port's own headers rather than everything in
+    /// `include_paths`.
+    pub headers: BTreeMap<String, Vec<PathBuf>>,
+
+    /// The files in `share/` that belong to each port, such as
+    /// `share/<port>/copyright`, `usage` and CMake config files.
+    pub share_files: BTreeMap<String, Vec<PathBuf>>,
+
+    /// Details of each of the ports from the vcpkg status database, for
+    /// generating a software bill of materials. See `Library::sbom_json`.
+    pub port_info: BTreeMap<String, PortInfo>,
+
     /// the vcpkg triplet that has been selected
     pub vcpkg_triplet: String,
 }
@@ -195,29 +417,140 @@ struct TargetTriplet {
     is_static: bool,
     lib_suffix: String,
     strip_lib_prefix: bool,
+    // dll, so or dylib
+    shared_lib_suffix: String,
 }
 
 impl<S: AsRef<str>> From<S> for TargetTriplet {
     fn from(triplet: S) -> TargetTriplet {
         let triplet = triplet.as_ref();
-        if triplet.contains("windows") {
+        if triplet.contains("mingw") {
+            // MinGW triplets produce libfoo.a, or libfoo.dll.a import libraries
+            // for libfoo.dll
+            let is_static = !triplet.contains("-dynamic");
+            TargetTriplet {
+                triplet: triplet.into(),
+                is_static: is_static,
+                lib_suffix: if is_static { "a" } else { "dll.a" }.into(),
+                strip_lib_prefix: true,
+                shared_lib_suffix: "dll".into(),
+            }
+        } else if triplet.contains("windows") {
             TargetTriplet {
                 triplet: triplet.into(),
                 is_static: triplet.contains("-static"),
                 lib_suffix: "lib".into(),
                 strip_lib_prefix: false,
+                shared_lib_suffix: "dll".into(),
             }
         } else {
+            let shared_lib_suffix = if triplet.contains("-osx") || triplet.contains("-ios") {
+                "dylib"
+            } else {
+                "so"
+            };
+            // custom triplets like x64-linux-dynamic that set VCPKG_LIBRARY_LINKAGE to dynamic
+            let is_static = !triplet.contains("-dynamic");
             TargetTriplet {
                 triplet: triplet.into(),
-                is_static: true,
-                lib_suffix: "a".into(),
+                is_static: is_static,
+                lib_suffix: if is_static { "a" } else { shared_lib_suffix }.into(),
                 strip_lib_prefix: true,
+                shared_lib_suffix: shared_lib_suffix.into(),
             }
         }
     }
 }
 
+impl TargetTriplet {
+    /// The suffix of the libraries to link for ports with the given linkage, which
+    /// can differ from the default linkage of the triplet.
+    fn lib_suffix_for(&self, is_static: bool) -> &str {
+        if !self.strip_lib_prefix {
+            "lib"
+        } else if is_static {
+            "a"
+        } else if self.shared_lib_suffix == "dll" {
+            "dll.a"
+        } else {
+            &self.shared_lib_suffix
+        }
+    }
+
+    /// The name of a static or import library without its suffix, or `None` if
+    /// the file is not a library for a port with the given linkage.
+    fn lib_stem_for<'a>(&self, filename: &'a str, is_static: bool) -> Option<&'a str> {
+        let lib_suffix = self.lib_suffix_for(is_static);
+        let suffix = format!(".{}", lib_suffix);
+        if is_static && filename.ends_with(".dll.a") {
+            // a MinGW import library
+            None
+        } else if filename.len() > suffix.len() && filename.ends_with(&suffix[..]) {
+            let stem = &filename[..filename.len() - suffix.len()];
+            // libz.1.dylib is only a versioned name for libz.dylib
+            let versioned = lib_suffix == "dylib"
+                && stem
+                    .rsplit('.')
+                    .next()
+                    .map(|v| stem.contains('.') && v.chars().all(|c| c.is_digit(10)))
+                    .unwrap_or(false);
+            if versioned {
+                None
+            } else {
+                Some(stem)
+            }
+        } else {
+            None
+        }
+    }
+
+    /// Shared libraries are found in `lib/` alongside any static libraries, as is
+    /// the case on Linux and macOS, rather than as DLLs in `bin/`.
+    fn shared_libs_in_lib_dir(&self) -> bool {
+        self.shared_lib_suffix != "dll"
+    }
+
+    /// Is `filename` in `lib/` a shared library that is needed at runtime, including
+    /// versioned names like `libz.so.1.2.11` or `libz.1.dylib`.
+    fn is_runtime_lib(&self, filename: &str) -> bool {
+        self.shared_libs_in_lib_dir()
+            && (filename.ends_with(&format!(".{}", self.shared_lib_suffix)[..])
+                || filename.contains(&format!(".{}.", self.shared_lib_suffix)[..]))
+    }
+
+    /// The name to record for a runtime library. Windows DLLs are recorded without
+    /// the `.dll` extension, other shared libraries by their full (versioned) name.
+    fn dll_stem<'a>(&self, filename: &'a str) -> &'a str {
+        if !self.shared_libs_in_lib_dir() && filename.ends_with(".dll") {
+            &filename[..filename.len() - 4]
+        } else {
+            filename
+        }
+    }
+
+    /// The name of the tool an executable in `tools/` provides, e.g. `protoc` for
+    /// `protoc.exe` on Windows. Executables have no extension on other platforms, so
+    /// files with one, such as scripts or data, are not tools there.
+    fn tool_name(&self, file: &Path) -> Option<String> {
+        let extension = file.extension().and_then(|e| e.to_str());
+        if !self.shared_libs_in_lib_dir() {
+            match extension {
+                Some(e) if e.eq_ignore_ascii_case("exe") => file
+                    .file_stem()
+                    .and_then(|s| s.to_str())
+                    .map(|s| s.to_owned()),
+                _ => None,
+            }
+        } else if extension.is_none() {
+            file.file_name()
+                .and_then(|s| s.to_str())
+                .map(|s| s.to_owned())
+        } else {
+            None
+        }
+    }
+}
+
 #[derive(Debug)] // need Display?
 pub enum Error {
     /// Aborted because of a `VCPKGRS_NO_*` environment variable.
@@ -228,7 +561,8 @@ pub enum Error {
     /// Aborted because a required environment variable was not set.
     RequiredEnvMissing(String),
 
-    /// On Windows, only MSVC ABI is supported
+    /// No vcpkg triplet is known for the Rust target. On Windows, only the MSVC
+    /// and GNU ABIs are supported.
     NotMSVC,
 
     /// Can't find a vcpkg tree
@@ -240,6 +574,15 @@ pub enum Error {
     /// Could not understand vcpkg installation
     VcpkgInstallation(String),
 
+    /// The vcpkg triplet links the C runtime differently to the Rust target, which
+    /// would likely cause LNK2038 errors when linking. Only returned if
+    /// `Config::crt_mismatch_is_error` is set.
+    CrtMismatch(String),
+
+    /// Another crate in the same build found a port in a different triplet or
+    /// installed directory, and `VCPKGRS_CONSISTENCY` is `error`.
+    Inconsistent(String),
+
     #[doc(hidden)]
     __Nonexhaustive,
 }
@@ -249,10 +592,12 @@ impl error::Error for Error {
         match *self {
             Error::DisabledByEnv(_) => "vcpkg-rs requested to be aborted",
             Error::RequiredEnvMissing(_) => "a required env setting is missing",
-            Error::NotMSVC => "vcpkg-rs only can only find libraries for MSVC ABI builds",
+            Error::NotMSVC => "vcpkg-rs can not find libraries for this target",
             Error::VcpkgNotFound(_) => "could not find Vcpkg tree",
             Error::LibNotFound(_) => "could not find library in Vcpkg tree",
             Error::VcpkgInstallation(_) => "could not look up details of packages in vcpkg tree",
+            Error::CrtMismatch(_) => "vcpkg triplet and Rust target link the C runtime differently",
+            Error::Inconsistent(_) => "crates in the same build found packages in different places",
             Error::__Nonexhaustive => panic!(),
         }
     }
@@ -272,7 +617,7 @@ impl fmt::Display for Error {
             Error::RequiredEnvMissing(ref name) => write!(f, "Aborted because {} is not set", name),
             Error::NotMSVC => write!(
                 f,
-                "the vcpkg-rs Vcpkg build helper can only find libraries built for the MSVC ABI."
+                "the vcpkg-rs Vcpkg build helper does not know which vcpkg triplet to use for this target."
             ),
             Error::VcpkgNotFound(ref detail) => write!(f, "Could not find Vcpkg tree: {}", detail),
             Error::LibNotFound(ref detail) => {
@@ -283,6 +628,10 @@ impl fmt::Display for Error {
                 "Could not look up details of packages in vcpkg tree {}",
                 detail
             ),
+            Error::CrtMismatch(ref detail) => write!(f, "C runtime linkage mismatch: {}", detail),
+            Error::Inconsistent(ref detail) => {
+                write!(f, "Packages found inconsistently by crates in this build: {}", detail)
+            }
             Error::__Nonexhaustive => panic!(),
         }
     }
@@ -418,6 +767,17 @@ fn find_vcpkg_target(cfg: &Config, target_triplet: &TargetTriplet) -> Result<Vcp
 
     let status_path = base.join("vcpkg");
 
+    let overlay_roots = if !cfg.overlay_installed_roots.is_empty() {
+        cfg.overlay_installed_roots.clone()
+    } else {
+        match env::var_os("VCPKGRS_OVERLAY_INSTALLED_ROOTS") {
+            Some(roots) => env::split_paths(&roots)
+                .filter(|root| !root.as_os_str().is_empty())
+                .collect(),
+            None => Vec::new(),
+        }
+    };
+
     base.push(&target_triplet.triplet);
 
     let lib_path = base.join("lib");
@@ -425,16 +785,315 @@ fn find_vcpkg_target(cfg: &Config, target_triplet: &TargetTriplet) -> Result<Vcp
     let include_path = base.join("include");
     let packages_path = vcpkg_root.join("packages");
 
+    // the triplet file is the authority on linkage, the name of the triplet is
+    // only a guess, which is used for anything the file can't be read for
+    let mut target_triplet = target_triplet.clone();
+    let mut triplet_warning = None;
+    let triplet_settings = match find_triplet_file(&vcpkg_root, &target_triplet.triplet) {
+        Some(path) => match TripletSettings::load(&path) {
+            Ok(settings) => {
+                if !settings.skipped_includes.is_empty() {
+                    triplet_warning = Some(format!(
+                        "cargo:warning=Could not follow include({}) in triplet file {}, \
+                         so the name of the triplet decides what they would set",
+                        settings.skipped_includes.join("), include("),
+                        path.display()
+                    ));
+                }
+                settings.apply(&mut target_triplet);
+                Some(settings)
+            }
+            Err(e) => {
+                triplet_warning = Some(format!(
+                    "cargo:warning=Could not read triplet file {}, so the name of the \
+                     triplet decides its settings: {}",
+                    path.display(),
+                    e
+                ));
+                None
+            }
+        },
+        None => None,
+    };
+
     Ok(VcpkgTarget {
         lib_path: lib_path,
         bin_path: bin_path,
         include_path: include_path,
         status_path: status_path,
         packages_path: packages_path,
-        target_triplet: target_triplet.clone(),
+        target_triplet: target_triplet,
+        triplet_settings: triplet_settings,
+        triplet_warning: triplet_warning,
+        overlay_roots: overlay_roots,
     })
 }
 
+/// Settings read from a vcpkg triplet file such as `triplets/x64-windows.cmake`.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct TripletSettings {
+    /// VCPKG_TARGET_ARCHITECTURE, e.g. x64
+    target_architecture: Option<String>,
+    /// VCPKG_CRT_LINKAGE, static or dynamic
+    crt_linkage: Option<String>,
+    /// VCPKG_LIBRARY_LINKAGE, static or dynamic
+    library_linkage: Option<String>,
+    /// VCPKG_CMAKE_SYSTEM_NAME, empty for Windows desktop
+    cmake_system_name: Option<String>,
+    /// VCPKG_BUILD_TYPE, release if no debug libraries are built
+    build_type: Option<String>,
+    /// include() arguments that could not be followed, such as ones using
+    /// `$ENV{...}` or files that are missing
+    skipped_includes: Vec<String>,
+}
+
+impl TripletSettings {
+    /// Read the triplet file at `path`, following any `include()` of other triplet files.
+    /// Includes that can't be followed are skipped and listed in `skipped_includes`.
+    fn load(path: &Path) -> std::io::Result<TripletSettings> {
+        let mut settings = TripletSettings::default();
+        try!(settings.load_file(path, 0));
+        Ok(settings)
+    }
+
+    fn load_file(&mut self, path: &Path, depth: usize) -> std::io::Result<()> {
+        let mut contents = String::new();
+        try!(File::open(path).and_then(|mut f| f.read_to_string(&mut contents)));
+
+        let list_dir = path.parent().unwrap_or(Path::new("")).to_string_lossy();
+        // settings inside if() blocks, such as the linkage of particular ports,
+        // are ignored, as what was installed for each port is what matters
+        let mut if_depth = 0;
+        for (command, args) in cmake_commands(&contents) {
+            match &command[..] {
+                "if" => if_depth += 1,
+                "endif" if if_depth > 0 => if_depth -= 1,
+                "set" if args.len() >= 2 && if_depth == 0 => self.set(&args[0], &args[1]),
+                // e.g. include(${CMAKE_CURRENT_LIST_DIR}/../x64-windows.cmake)
+                "include" if args.len() >= 1 && if_depth == 0 && depth < 8 => {
+                    let included = args[0].replace("${CMAKE_CURRENT_LIST_DIR}", &list_dir);
+                    // other variables are only known to cmake
+                    if included.contains('$')
+                        || self.load_file(Path::new(&included), depth + 1).is_err()
+                    {
+                        self.skipped_includes.push(args[0].clone());
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn set(&mut self, name: &str, value: &str) {
+        let value = Some(value.to_owned());
+        match name {
+            "VCPKG_TARGET_ARCHITECTURE" => self.target_architecture = value,
+            "VCPKG_CRT_LINKAGE" => self.crt_linkage = value,
+            "VCPKG_LIBRARY_LINKAGE" => self.library_linkage = value,
+            "VCPKG_CMAKE_SYSTEM_NAME" => self.cmake_system_name = value,
+            "VCPKG_BUILD_TYPE" => self.build_type = value,
+            _ => {}
+        }
+    }
+
+    /// Are debug versions of the libraries built as well as the release versions
+    fn has_debug(&self) -> bool {
+        self.build_type
+            .as_ref()
+            .map(|t| t != "release")
+            .unwrap_or(true)
+    }
+
+    /// Update the linkage and library naming of `triplet` to match these settings.
+    fn apply(&self, triplet: &mut TargetTriplet) {
+        if let Some(ref linkage) = self.library_linkage {
+            triplet.is_static = linkage != "dynamic";
+        }
+        let system = self
+            .cmake_system_name
+            .as_ref()
+            .map(|s| &s[..])
+            .unwrap_or("");
+        let (shared_lib_suffix, strip_lib_prefix) = match system {
+            "" | "WindowsStore" => ("dll", false),
+            "MinGW" => ("dll", true),
+            "Darwin" | "iOS" => ("dylib", true),
+            _ => ("so", true),
+        };
+        triplet.shared_lib_suffix = shared_lib_suffix.into();
+        triplet.strip_lib_prefix = strip_lib_prefix;
+        triplet.lib_suffix = triplet.lib_suffix_for(triplet.is_static).to_owned();
+    }
+}
+
+/// Split CMake source into commands and their arguments. Only the simple single
+/// line commands used in triplet files are understood.
+fn cmake_commands(contents: &str) -> Vec<(String, Vec<String>)> {
+    let mut commands = Vec::new();
+    for line in contents.lines() {
+        let line = line.split('#').next().unwrap().trim();
+        let open = match line.find('(') {
+            Some(open) => open,
+            None => continue,
+        };
+        let command = line[..open].trim().to_ascii_lowercase();
+        let args = line[open + 1..].trim_right().trim_right_matches(')');
+        commands.push((
+            command,
+            args.split_whitespace()
+                .map(|a| a.trim_matches('"').to_owned())
+                .collect(),
+        ));
+    }
+    commands
+}
+
+/// Find the triplet file for `triplet`, looking in `VCPKG_OVERLAY_TRIPLETS` before
+/// the triplets that come with vcpkg.
+fn find_triplet_file(vcpkg_root: &Path, triplet: &str) -> Option<PathBuf> {
+    let file_name = format!("{}.cmake", triplet);
+    let mut dirs = Vec::new();
+    if let Some(overlays) = env::var_os("VCPKG_OVERLAY_TRIPLETS") {
+        dirs.extend(env::split_paths(&overlays));
+    }
+    dirs.push(vcpkg_root.join("triplets"));
+    dirs.push(vcpkg_root.join("triplets").join("community"));
+
+    dirs.into_iter()
+        .map(|dir| dir.join(&file_name))
+        .find(|path| path.is_file())
+}
+
+// Describe how the triplet links the MSVC C runtime differently to the Rust
+// target, if it does. Mixing them often results in LNK2038 errors that don't
+// make the cause obvious. This can only be checked when running under cargo.
+fn crt_mismatch(vcpkg_target: &VcpkgTarget) -> Option<String> {
+    let triplet = &vcpkg_target.target_triplet;
+    if triplet.strip_lib_prefix {
+        // only MSVC has the choice of C runtime baked into the libraries
+        return None;
+    }
+    let rust_crt_static = match env::var("CARGO_CFG_TARGET_FEATURE") {
+        Ok(features) => features.split(',').any(|f| f == "crt-static"),
+        Err(_) => return None,
+    };
+    let triplet_crt_static = match vcpkg_target
+        .triplet_settings
+        .as_ref()
+        .and_then(|s| s.crt_linkage.as_ref())
+    {
+        Some(linkage) => linkage == "static",
+        // the convention for the built in and community triplets
+        None => triplet.triplet.contains("-static") && !triplet.triplet.contains("-static-md"),
+    };
+
+    if rust_crt_static == triplet_crt_static {
+        None
+    } else if triplet_crt_static {
+        Some(format!(
+            "vcpkg triplet {} links the C runtime statically, but the Rust target \
+             links it dynamically. Build with RUSTFLAGS=-Ctarget-feature=+crt-static \
+             or use a triplet with VCPKG_CRT_LINKAGE dynamic such as {}-windows-static-md.",
+            triplet.triplet,
+            triplet_arch(vcpkg_target)
+        ))
+    } else {
+        Some(format!(
+            "vcpkg triplet {} links the C runtime dynamically, but the Rust target \
+             links it statically because of +crt-static. Build without \
+             -Ctarget-feature=+crt-static or use a triplet with VCPKG_CRT_LINKAGE static \
+             such as {}-windows-static.",
+            triplet.triplet,
+            triplet_arch(vcpkg_target)
+        ))
+    }
+}
+
+// The architecture of the triplet, if the triplet file or the name says so.
+fn known_triplet_arch(vcpkg_target: &VcpkgTarget) -> Option<String> {
+    if let Some(arch) = vcpkg_target
+        .triplet_settings
+        .as_ref()
+        .and_then(|s| s.target_architecture.clone())
+    {
+        return Some(arch);
+    }
+    let name_arch = vcpkg_target
+        .target_triplet
+        .triplet
+        .split('-')
+        .next()
+        .unwrap_or("");
+    ARCH_TRIPLETS
+        .iter()
+        .find(|&&(_, arch)| arch == name_arch)
+        .map(|&(_, arch)| arch.to_owned())
+}
+
+// The operating system that a triplet builds for, using the names in vcpkg
+// triplets, e.g. windows or osx. This is taken from the triplet file if there is
+// one, or else from the name of the triplet. None if it is not known.
+fn known_triplet_os(vcpkg_target: &VcpkgTarget) -> Option<String> {
+    if let Some(ref settings) = vcpkg_target.triplet_settings {
+        let system = settings
+            .cmake_system_name
+            .as_ref()
+            .map(|s| &s[..])
+            .unwrap_or("");
+        return Some(
+            match system {
+                "" | "WindowsStore" | "MinGW" => "windows",
+                "Darwin" => "osx",
+                _ => system,
+            }
+            .to_ascii_lowercase(),
+        );
+    }
+    vcpkg_target
+        .target_triplet
+        .triplet
+        .split('-')
+        .skip(1)
+        .filter_map(|part| match part {
+            "windows" | "uwp" | "mingw" => Some("windows"),
+            "osx" | "linux" | "android" | "ios" | "freebsd" | "openbsd" | "emscripten" => {
+                Some(part)
+            }
+            _ => None,
+        })
+        .next()
+        .map(|os| os.to_owned())
+}
+
+// The operating system of the Rust target, using the names in vcpkg triplets.
+fn rust_target_os() -> Option<String> {
+    let target = RustTarget::from_env();
+    if target.arch == "wasm32" {
+        return Some("emscripten".to_owned());
+    }
+    if target.os == "windows" {
+        return Some("windows".to_owned());
+    }
+    lookup(OS_TRIPLETS, &target.os).map(|os| os.to_owned())
+}
+
+fn triplet_arch(vcpkg_target: &VcpkgTarget) -> String {
+    vcpkg_target
+        .triplet_settings
+        .as_ref()
+        .and_then(|s| s.target_architecture.clone())
+        .unwrap_or(
+            vcpkg_target
+                .target_triplet
+                .triplet
+                .split('-')
+                .next()
+                .unwrap_or("x64")
+                .to_owned(),
+        )
+}
+
 /// Parsed knowledge from a .pc file.
 #[derive(Debug)]
 struct PcFile {
@@ -611,14 +1270,42 @@ impl PcFiles {
 
 #[derive(Clone, Debug)]
 struct Port {
-    // dlls if any
+    // version, as used in the name of the port's .list file
+    version: String,
+
+    // Port-Version from the status file, if the port has been revised
+    port_version: String,
+
+    // Abi from the status file, the hash vcpkg uses to identify the build
+    abi: String,
+
+    // dlls if any, only filled in once the port manifest has been loaded
     dlls: Vec<String>,
 
-    // libs (static or import)
+    // libs (static or import), only filled in once the port manifest has been loaded
     libs: Vec<String>,
 
     // ports that this port depends on
     deps: Vec<String>,
+
+    // linkage, only known once the port manifest has been loaded
+    is_static: bool,
+}
+
+// The files of interest in the .list file of a port.
+struct PortManifest {
+    // dlls in bin/, or shared libraries in lib/ on Linux and macOS
+    dlls: Vec<String>,
+    // libs (static or import) in lib/
+    libs: Vec<String>,
+    // executables in tools/, by name
+    tools: Vec<(String, PathBuf)>,
+    // files in include/
+    headers: Vec<PathBuf>,
+    // files in share/
+    share_files: Vec<PathBuf>,
+    // every file in the manifest
+    files: Vec<PathBuf>,
 }
 
 fn load_port_manifest(
@@ -626,7 +1313,7 @@ fn load_port_manifest(
     port: &str,
     version: &str,
     vcpkg_target: &VcpkgTarget,
-) -> Result<(Vec<String>, Vec<String>), Error> {
+) -> Result<PortManifest, Error> {
     let manifest_file = path.join("info").join(format!(
         "{}_{}_{}.list",
         port, version, vcpkg_target.target_triplet.triplet
@@ -634,6 +1321,10 @@ fn load_port_manifest(
 
     let mut dlls = Vec::new();
     let mut libs = Vec::new();
+    let mut tools = Vec::new();
+    let mut headers = Vec::new();
+    let mut share_files = Vec::new();
+    let mut files = Vec::new();
 
     let f = try!(
         File::open(&manifest_file).map_err(|_| Error::VcpkgInstallation(format!(
@@ -646,13 +1337,39 @@ fn load_port_manifest(
 
     let dll_prefix = Path::new(&vcpkg_target.target_triplet.triplet).join("bin");
     let lib_prefix = Path::new(&vcpkg_target.target_triplet.triplet).join("lib");
+    let tool_prefix = Path::new(&vcpkg_target.target_triplet.triplet).join("tools");
+    let include_prefix = Path::new(&vcpkg_target.target_triplet.triplet).join("include");
+    let share_prefix = Path::new(&vcpkg_target.target_triplet.triplet).join("share");
+    let installed_path = path.parent().unwrap_or(path);
 
     for line in file.lines() {
         let line = line.unwrap();
 
         let file_path = Path::new(&line);
 
-        if let Ok(dll) = file_path.strip_prefix(&dll_prefix) {
+        if line.ends_with('/') {
+            // directories are listed as well as files
+            continue;
+        }
+        files.push(installed_path.join(file_path));
+
+        if file_path.starts_with(&include_prefix) {
+            headers.push(installed_path.join(file_path));
+        } else if file_path.starts_with(&share_prefix) {
+            share_files.push(installed_path.join(file_path));
+        } else if let Ok(tool) = file_path.strip_prefix(&tool_prefix) {
+            // tools are usually in "tools/<port>/" but some older ports put
+            // them straight into "tools/"
+            let in_tool_dir = match tool.parent() {
+                Some(dir) => dir == Path::new("") || dir == Path::new(port),
+                None => false,
+            };
+            if in_tool_dir {
+                if let Some(name) = vcpkg_target.target_triplet.tool_name(tool) {
+                    tools.push((name, installed_path.join(file_path)));
+                }
+            }
+        } else if let Ok(dll) = file_path.strip_prefix(&dll_prefix) {
             if dll.extension() == Some(OsStr::new("dll"))
                 && dll.components().collect::<Vec<_>>().len() == 1
             {
@@ -661,7 +1378,27 @@ fn load_port_manifest(
                 dll.to_str().map(|s| dlls.push(s.to_owned()));
             }
         } else if let Ok(lib) = file_path.strip_prefix(&lib_prefix) {
-            if lib.extension() == Some(OsStr::new(&vcpkg_target.target_triplet.lib_suffix))
+            if vcpkg_target.target_triplet.shared_libs_in_lib_dir()
+                && lib.components().collect::<Vec<_>>().len() == 1
+            {
+                // on Linux and macOS the shared libraries and their versioned
+                // symlinks are all needed at runtime
+                if let Some(name) = lib.to_str() {
+                    if vcpkg_target.target_triplet.is_runtime_lib(name) {
+                        dlls.push(name.to_owned());
+                    }
+                }
+            }
+            // either linkage is accepted here, as the triplet can override the
+            // linkage of individual ports
+            let triplet = &vcpkg_target.target_triplet;
+            if lib
+                .to_str()
+                .map(|s| {
+                    triplet.lib_stem_for(s, true).is_some()
+                        || triplet.lib_stem_for(s, false).is_some()
+                })
+                .unwrap_or(false)
                 && lib.components().collect::<Vec<_>>().len() == 1
             {
                 if let Some(lib) = vcpkg_target.link_name_for_lib(lib) {
@@ -683,7 +1420,30 @@ fn load_port_manifest(
         libs = pc_files.fix_ordering(libs);
     }
 
-    Ok((dlls, libs))
+    Ok(PortManifest {
+        dlls: dlls,
+        libs: libs,
+        tools: tools,
+        headers: headers,
+        share_files: share_files,
+        files: files,
+    })
+}
+
+// Decide whether a port was built as a static or dynamic library from what it
+// installed, as triplets can set the linkage of individual ports and ports can
+// refuse to build with the linkage the triplet asks for.
+fn port_is_static(dlls: &[String], libs: &[String], triplet: &TargetTriplet) -> bool {
+    if !dlls.is_empty() {
+        return false;
+    }
+    match libs.first() {
+        // libraries without a DLL, which are static unless they are import
+        // libraries for something outside of vcpkg
+        Some(lib) => triplet.lib_stem_for(lib, true).is_some(),
+        // header-only ports need nothing at runtime
+        None => true,
+    }
 }
 
 // load ports from the status file or one of the incremental updates
@@ -805,17 +1565,20 @@ fn load_ports(target: &VcpkgTarget) -> Result<BTreeMap<String, Port>, Error> {
             {
                 match (current.get("Version"), feature) {
                     (Some(version), _) => {
-                        // this failing here and bailing out causes everything to fail
-                        let lib_info = try!(load_port_manifest(
-                            &target.status_path,
-                            &name,
-                            version,
-                            &target
-                        ));
+                        // the port manifest is not loaded until it is known that the
+                        // port is required, so a problem with an unrelated port can't
+                        // cause the lookup to fail
                         let port = Port {
-                            dlls: lib_info.0,
-                            libs: lib_info.1,
+                            version: version.clone(),
+                            port_version: current
+                                .get("Port-Version")
+                                .cloned()
+                                .unwrap_or(String::new()),
+                            abi: current.get("Abi").cloned().unwrap_or(String::new()),
+                            dlls: Vec::new(),
+                            libs: Vec::new(),
                             deps: deps,
+                            is_static: true,
                         };
 
                         ports.insert(name.to_string(), port);
@@ -825,14 +1588,19 @@ fn load_ports(target: &VcpkgTarget) -> Result<BTreeMap<String, Port>, Error> {
                             port.deps.append(&mut deps);
                         }
                         _ => {
-                            println!("found a feature that had no corresponding port :-");
-                            println!("current {:+?}", current);
+                            println!(
+                                "cargo:warning=vcpkg status has feature {} for port {} \
+                                 which is not installed",
+                                _feature, name
+                            );
                             continue;
                         }
                     },
                     (_, _) => {
-                        println!("didn't know how to deal with status file entry :-");
-                        println!("{:+?}", current);
+                        println!(
+                            "cargo:warning=vcpkg status entry for {} has no version, ignoring it",
+                            name
+                        );
                         continue;
                     }
                 }
@@ -843,7 +1611,276 @@ fn load_ports(target: &VcpkgTarget) -> Result<BTreeMap<String, Port>, Error> {
     Ok(ports)
 }
 
+// Parsed port tables are cached per thread, keyed by the status directory and
+// the triplet. Build scripts often call find_package several times and reading
+// the status database is the bulk of the work for large trees.
+thread_local! {
+    static PORT_CACHE: RefCell<HashMap<(PathBuf, String), CachedPorts>> =
+        RefCell::new(HashMap::new());
+}
+
+#[derive(Clone, Debug)]
+struct CachedPorts {
+    // identifies the state of the status database when the ports were loaded
+    stamp: Vec<String>,
+    ports: BTreeMap<String, Port>,
+}
+
+const PORT_CACHE_HEADER: &'static str = "vcpkg-rs port cache 3";
+
+// Describe the files making up the status database so that a cached port
+// table can be discarded if vcpkg has installed or removed anything since.
+// Returns None if the status database can't be examined, in which case
+// load_ports will report the problem.
+fn status_stamp(target: &VcpkgTarget) -> Option<Vec<String>> {
+    let mut files = vec![target.status_path.join("status")];
+
+    let status_update_dir = target.status_path.join("updates");
+    let mut updates = match fs::read_dir(&status_update_dir) {
+        Ok(dir) => match dir
+            .map(|rde| rde.map(|de| de.path()))
+            .collect::<Result<Vec<_>, _>>()
+        {
+            Ok(paths) => paths,
+            Err(_) => return None,
+        },
+        Err(_) => return None,
+    };
+    updates.sort();
+    files.extend(updates);
+
+    let mut stamp = Vec::new();
+    for file in &files {
+        match fs::metadata(file) {
+            Ok(meta) => {
+                let modified = meta
+                    .modified()
+                    .ok()
+                    .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                    .map(|d| format!("{}.{:09}", d.as_secs(), d.subsec_nanos()))
+                    .unwrap_or(String::new());
+                stamp.push(format!("{}\t{}\t{}", file.display(), meta.len(), modified));
+            }
+            // the main status file is optional
+            Err(_) => stamp.push(format!("{}\tmissing", file.display())),
+        }
+    }
+    Some(stamp)
+}
+
+// The file the port table of a triplet in an installed directory is persisted in.
+// Overlay installed directories can have the same triplet, so the name includes a
+// hash of the directory.
+fn port_cache_file(target: &VcpkgTarget, out_dir: &Path) -> PathBuf {
+    let root_hash: String = sha256(target.status_path.to_string_lossy().as_bytes())[..8]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    out_dir.join(format!(
+        "vcpkg-rs-ports-{}-{}.cache",
+        target.target_triplet.triplet, root_hash
+    ))
+}
+
+fn write_port_cache(path: &Path, status_path: &Path, cached: &CachedPorts) -> Result<(), Error> {
+    let mut out = String::new();
+    out.push_str(PORT_CACHE_HEADER);
+    out.push('\n');
+    out.push_str(&format!("root\t{}\n", status_path.display()));
+    for line in &cached.stamp {
+        out.push_str(&format!("stamp\t{}\n", line));
+    }
+    for (name, port) in &cached.ports {
+        out.push_str(&format!("port\t{}\n", name));
+        out.push_str(&format!("version\t{}\n", port.version));
+        out.push_str(&format!("port-version\t{}\n", port.port_version));
+        out.push_str(&format!("abi\t{}\n", port.abi));
+        for dep in &port.deps {
+            out.push_str(&format!("dep\t{}\n", dep));
+        }
+    }
+
+    let mut f = try!(
+        File::create(path).map_err(|e| Error::VcpkgInstallation(format!(
+            "Could not create port cache {}: {}",
+            path.display(),
+            e
+        )))
+    );
+    f.write_all(out.as_bytes()).map_err(|e| {
+        Error::VcpkgInstallation(format!(
+            "Could not write port cache {}: {}",
+            path.display(),
+            e
+        ))
+    })
+}
+
+// Read a persisted port table. Returns None if the file is missing, was written
+// for a different tree or is in a format that is not understood.
+fn read_port_cache(path: &Path, status_path: &Path) -> Option<CachedPorts> {
+    let mut contents = String::new();
+    if File::open(path)
+        .and_then(|mut f| f.read_to_string(&mut contents))
+        .is_err()
+    {
+        return None;
+    }
+
+    let root_line = format!("root\t{}", status_path.display());
+    let mut lines = contents.lines();
+    if lines.next() != Some(PORT_CACHE_HEADER) {
+        return None;
+    }
+    if lines.next() != Some(&root_line[..]) {
+        return None;
+    }
+
+    let mut stamp = Vec::new();
+    let mut ports = BTreeMap::new();
+    let mut current: Option<(String, Port)> = None;
+    for line in lines {
+        let mut parts = line.splitn(2, '\t');
+        match (parts.next(), parts.next()) {
+            (Some("stamp"), Some(value)) => stamp.push(value.to_owned()),
+            (Some("port"), Some(name)) => {
+                if let Some((name, port)) = current.take() {
+                    ports.insert(name, port);
+                }
+                current = Some((
+                    name.to_owned(),
+                    Port {
+                        version: String::new(),
+                        port_version: String::new(),
+                        abi: String::new(),
+                        dlls: Vec::new(),
+                        libs: Vec::new(),
+                        deps: Vec::new(),
+                        is_static: true,
+                    },
+                ));
+            }
+            (Some(kind), Some(value)) => match current {
+                Some((_, ref mut port)) => match kind {
+                    "version" => port.version = value.to_owned(),
+                    "port-version" => port.port_version = value.to_owned(),
+                    "abi" => port.abi = value.to_owned(),
+                    "dep" => port.deps.push(value.to_owned()),
+                    _ => return None,
+                },
+                None => return None,
+            },
+            _ => return None,
+        }
+    }
+    if let Some((name, port)) = current.take() {
+        ports.insert(name, port);
+    }
+
+    Some(CachedPorts {
+        stamp: stamp,
+        ports: ports,
+    })
+}
+
+// load_ports, but reuse the result of an earlier call if the status database
+// has not changed since
+fn load_ports_cached(
+    target: &VcpkgTarget,
+    persistent: bool,
+) -> Result<BTreeMap<String, Port>, Error> {
+    let stamp = match status_stamp(target) {
+        Some(stamp) => stamp,
+        None => return load_ports(target),
+    };
+    let key = (
+        target.status_path.clone(),
+        target.target_triplet.triplet.clone(),
+    );
+
+    let hit = PORT_CACHE.with(|cache| match cache.borrow().get(&key) {
+        Some(cached) if cached.stamp == stamp => Some(cached.ports.clone()),
+        _ => None,
+    });
+    if let Some(ports) = hit {
+        return Ok(ports);
+    }
+
+    let out_dir = if persistent {
+        env::var_os("OUT_DIR").map(PathBuf::from)
+    } else {
+        None
+    };
+
+    let persisted = out_dir
+        .as_ref()
+        .and_then(|dir| read_port_cache(&port_cache_file(target, dir), &target.status_path))
+        .and_then(|cached| {
+            if cached.stamp == stamp {
+                Some(cached)
+            } else {
+                None
+            }
+        });
+
+    let cached = match persisted {
+        Some(cached) => cached,
+        None => {
+            let cached = CachedPorts {
+                stamp: stamp,
+                ports: try!(load_ports(target)),
+            };
+            if let Some(ref dir) = out_dir {
+                // failing to write the cache only costs time on the next run
+                if let Err(e) =
+                    write_port_cache(&port_cache_file(target, dir), &target.status_path, &cached)
+                {
+                    println!("cargo:warning={}", e);
+                }
+            }
+            cached
+        }
+    };
+
+    let ports = cached.ports.clone();
+    PORT_CACHE.with(|cache| {
+        cache.borrow_mut().insert(key, cached);
+    });
+    Ok(ports)
+}
+
+// The ports of all of the installed directories of a triplet.
+struct InstalledPorts {
+    // the port from the first installed directory that has it
+    ports: BTreeMap<String, Port>,
+    // the index into the roots of the installed directory each port is from
+    roots: BTreeMap<String, usize>,
+    // ports that are hidden by a port of the same name in an earlier
+    // installed directory, with the index of the root and the version
+    shadowed: Vec<(String, usize, String)>,
+}
+
+fn load_installed_ports(roots: &[VcpkgTarget], persistent: bool) -> Result<InstalledPorts, Error> {
+    let mut installed = InstalledPorts {
+        ports: BTreeMap::new(),
+        roots: BTreeMap::new(),
+        shadowed: Vec::new(),
+    };
+    for (index, root) in roots.iter().enumerate() {
+        for (port_name, port) in try!(load_ports_cached(root, persistent)) {
+            if installed.ports.contains_key(&port_name) {
+                installed.shadowed.push((port_name, index, port.version));
+            } else {
+                installed.roots.insert(port_name.clone(), index);
+                installed.ports.insert(port_name, port);
+            }
+        }
+    }
+    Ok(installed)
+}
+
 /// paths and triple for the chosen target
+#[derive(Clone)]
 struct VcpkgTarget {
     lib_path: PathBuf,
     bin_path: PathBuf,
@@ -856,11 +1893,49 @@ struct VcpkgTarget {
 
     // target-specific settings.
     target_triplet: TargetTriplet,
+
+    // contents of the triplet file, if one was found
+    triplet_settings: Option<TripletSettings>,
+
+    // cargo:warning= line if the triplet file could not be read completely
+    triplet_warning: Option<String>,
+
+    // installed directories that are searched before this one
+    overlay_roots: Vec<PathBuf>,
 }
 
 impl VcpkgTarget {
-    fn link_name_for_lib(&self, filename: &std::path::Path) -> Option<String> {
-        if self.target_triplet.strip_lib_prefix {
+    // The same triplet in each of the installed directories, in the order they
+    // are searched: the overlays first, then the main installed directory.
+    fn roots(&self) -> Vec<VcpkgTarget> {
+        let mut roots: Vec<VcpkgTarget> = self
+            .overlay_roots
+            .iter()
+            .map(|root| {
+                let triplet_dir = root.join(&self.target_triplet.triplet);
+                VcpkgTarget {
+                    lib_path: triplet_dir.join("lib"),
+                    bin_path: triplet_dir.join("bin"),
+                    include_path: triplet_dir.join("include"),
+                    status_path: root.join("vcpkg"),
+                    overlay_roots: Vec::new(),
+                    ..self.clone()
+                }
+            })
+            .collect();
+        roots.push(VcpkgTarget {
+            overlay_roots: Vec::new(),
+            ..self.clone()
+        });
+        roots
+    }
+
+    fn installed_root(&self) -> &Path {
+        self.status_path.parent().unwrap()
+    }
+
+    fn link_name_for_lib(&self, filename: &std::path::Path) -> Option<String> {
+        if self.target_triplet.strip_lib_prefix {
             filename.to_str().map(|s| s.to_owned())
         // filename
         //     .to_str()
@@ -893,6 +1968,113 @@ impl Config {
         Ok(self.target.as_ref().unwrap().clone())
     }
 
+    // The triplets to look in, starting with the selected triplet and followed by
+    // the candidates from the config or VCPKGRS_TRIPLET_CANDIDATES.
+    fn get_triplet_candidates(&self, preferred: TargetTriplet) -> Vec<TargetTriplet> {
+        let mut candidates: Vec<TargetTriplet> = if !self.triplet_candidates.is_empty() {
+            self.triplet_candidates.clone()
+        } else {
+            env::var("VCPKGRS_TRIPLET_CANDIDATES")
+                .unwrap_or(String::new())
+                .split(',')
+                .map(|s| s.trim())
+                .filter(|s| !s.is_empty())
+                .map(|s| s.into())
+                .collect()
+        };
+        candidates.insert(0, preferred);
+        let mut unique: Vec<TargetTriplet> = Vec::new();
+        for candidate in candidates {
+            if !unique.iter().any(|u| u.triplet == candidate.triplet) {
+                unique.push(candidate);
+            }
+        }
+        unique
+    }
+
+    // A mismatch between the C runtime linkage of the triplet and the Rust target
+    // is a warning, as the link can work if the libraries don't use the C runtime
+    // in incompatible ways, unless the config makes it an error.
+    fn check_crt_linkage(&self, vcpkg_target: &VcpkgTarget) -> Result<Option<String>, Error> {
+        match crt_mismatch(vcpkg_target) {
+            Some(detail) => {
+                if self.crt_mismatch_is_error {
+                    Err(Error::CrtMismatch(detail))
+                } else {
+                    Ok(Some(format!("cargo:warning={}", detail)))
+                }
+            }
+            None => Ok(None),
+        }
+    }
+
+    // Pick the first of the candidate triplets that is compatible with the Rust
+    // target and has the port installed.
+    fn select_vcpkg_target(
+        &self,
+        port_name: &str,
+        preferred: TargetTriplet,
+    ) -> Result<VcpkgTarget, Error> {
+        let candidates = self.get_triplet_candidates(preferred);
+        if candidates.len() == 1 {
+            return find_vcpkg_target(&self, &candidates[0]);
+        }
+
+        let mut arch = None;
+        let target_os = rust_target_os();
+        let mut tried = Vec::new();
+        for candidate in &candidates {
+            let vcpkg_target = try!(find_vcpkg_target(&self, candidate));
+            let candidate_arch = known_triplet_arch(&vcpkg_target);
+            if arch.is_none() {
+                arch = candidate_arch.clone();
+            }
+
+            let reason = if candidate_arch.is_some() && arch.is_some() && candidate_arch != arch {
+                "different architecture"
+            } else if target_os.is_some()
+                && known_triplet_os(&vcpkg_target)
+                    .map(|os| Some(os) != target_os)
+                    .unwrap_or(false)
+            {
+                "different operating system"
+            } else if crt_mismatch(&vcpkg_target).is_some() {
+                "different C runtime linkage"
+            } else if !vcpkg_target.target_triplet.is_static
+                && !env::var_os("VCPKGRS_DYNAMIC").is_some()
+            {
+                "dynamic linkage needs VCPKGRS_DYNAMIC"
+            } else if !try!(self.is_installed(port_name, &vcpkg_target)) {
+                "not installed"
+            } else {
+                return Ok(vcpkg_target);
+            };
+            tried.push(format!("{} ({})", candidate.triplet, reason));
+        }
+
+        Err(Error::LibNotFound(format!(
+            "package {} was not found in any of the candidate vcpkg triplets: {}",
+            port_name,
+            tried.join(", ")
+        )))
+    }
+
+    fn is_installed(&self, port_name: &str, vcpkg_target: &VcpkgTarget) -> Result<bool, Error> {
+        let roots = vcpkg_target.roots();
+        if self.required_libs.is_empty() {
+            let installed = try!(load_installed_ports(&roots, self.persistent_cache));
+            Ok(installed.ports.contains_key(port_name))
+        } else {
+            Ok(self.required_libs.iter().all(|required_lib| {
+                roots.iter().any(|root| {
+                    root.lib_path
+                        .join(required_lib.clone() + "." + &vcpkg_target.target_triplet.lib_suffix)
+                        .exists()
+                })
+            }))
+        }
+    }
+
     /// Find the package `port_name` in a Vcpkg tree.
     ///
     /// Emits cargo metadata to link to libraries provided by the Vcpkg package/port
@@ -928,13 +2110,28 @@ impl Config {
             return Err(Error::DisabledByEnv(abort_var_name));
         }
 
-        let vcpkg_target = try!(find_vcpkg_target(&self, &msvc_target));
+        let vcpkg_target = try!(self.select_vcpkg_target(port_name, msvc_target));
+        let crt_warning = try!(self.check_crt_linkage(&vcpkg_target));
+        let roots = vcpkg_target.roots();
+        // the installed directories that ports were found in, or all of them if the
+        // libraries to link were given explicitly
+        let mut used_roots: Vec<usize> = (0..roots.len()).collect();
+        let mut conflicts = Vec::new();
         let mut required_port_order = Vec::new();
+        let mut dynamic_ports = Vec::new();
+        let mut tools = BTreeMap::new();
+        let mut headers = BTreeMap::new();
+        let mut share_files = BTreeMap::new();
+        let mut port_info = BTreeMap::new();
+        let mut dll_owners = BTreeMap::new();
+        let mut file_problems = BTreeMap::new();
+        let mut file_warnings = Vec::new();
 
         // if no overrides have been selected, then the Vcpkg port name
         // is the the .lib name and the .dll name
         if self.required_libs.is_empty() {
-            let ports = try!(load_ports(&vcpkg_target));
+            let installed = try!(load_installed_ports(&roots, self.persistent_cache));
+            let ports = &installed.ports;
 
             if !ports.contains_key(port_name) {
                 return Err(Error::LibNotFound(format!(
@@ -944,31 +2141,12 @@ impl Config {
                 )));
             }
 
+            required_port_order = port_closure(ports, port_name);
             // the complete set of ports required
-            let mut required_ports: BTreeMap<String, Port> = BTreeMap::new();
-            // working of ports that we need to include
-            //        let mut ports_to_scan: BTreeSet<String> = BTreeSet::new();
-            //        ports_to_scan.insert(port_name.to_owned());
-            let mut ports_to_scan = vec![port_name.to_owned()]; //: Vec<String> = BTreeSet::new();
-
-            while !ports_to_scan.is_empty() {
-                let port_name = ports_to_scan.pop().unwrap();
-
-                if required_ports.contains_key(&port_name) {
-                    continue;
-                }
-
-                if let Some(port) = ports.get(&port_name) {
-                    for dep in &port.deps {
-                        ports_to_scan.push(dep.clone());
-                    }
-                    required_ports.insert(port_name.clone(), (*port).clone());
-                    remove_item(&mut required_port_order, &port_name);
-                    required_port_order.push(port_name);
-                } else {
-                    // what?
-                }
-            }
+            let mut required_ports: BTreeMap<String, Port> = required_port_order
+                .iter()
+                .map(|port_name| (port_name.clone(), ports[port_name].clone()))
+                .collect();
 
             // for port in ports {
             //     println!("port {:?}", port);
@@ -982,27 +2160,122 @@ impl Config {
             //     println!("required port {:?}", port);
             // }
 
-            // if no overrides have been selected, then the Vcpkg port name
-            // is the the .lib name and the .dll name
-            if self.required_libs.is_empty() {
-                for port_name in &required_port_order {
-                    let port = required_ports.get(port_name).unwrap();
-                    self.required_libs.extend(port.libs.iter().map(|s| {
-                        Path::new(&s)
-                            .file_stem()
-                            .unwrap()
-                            .to_string_lossy()
-                            .into_owned()
-                    }));
-                    self.required_dlls
-                        .extend(port.dlls.iter().cloned().map(|s| {
-                            Path::new(&s)
-                                .file_stem()
-                                .unwrap()
-                                .to_string_lossy()
-                                .into_owned()
-                        }));
+            used_roots.retain(|index| {
+                required_port_order
+                    .iter()
+                    .any(|port_name| installed.roots[port_name] == *index)
+            });
+            for &(ref port_name, index, ref version) in &installed.shadowed {
+                if required_ports.contains_key(port_name) {
+                    let used = &roots[installed.roots[port_name]];
+                    conflicts.push(format!(
+                        "cargo:warning=package {} is installed for vcpkg triplet {} in both {} \
+                         ({}) and {} ({}), using the one in {}",
+                        port_name,
+                        vcpkg_target.target_triplet.triplet,
+                        used.installed_root().display(),
+                        required_ports[port_name].version,
+                        roots[index].installed_root().display(),
+                        version,
+                        used.installed_root().display()
+                    ));
+                }
+            }
+
+            // only the manifests of the ports that are actually required are read
+            for port_name in &required_port_order {
+                let port = required_ports.get_mut(port_name).unwrap();
+                let root = &roots[installed.roots[port_name]];
+                let manifest = try!(load_port_manifest(
+                    &root.status_path,
+                    port_name,
+                    &port.version,
+                    root
+                ));
+                port.is_static =
+                    port_is_static(&manifest.dlls, &manifest.libs, &vcpkg_target.target_triplet);
+                port.libs = manifest
+                    .libs
+                    .into_iter()
+                    .filter(|l| {
+                        vcpkg_target
+                            .target_triplet
+                            .lib_stem_for(l, port.is_static)
+                            .is_some()
+                    })
+                    .collect();
+                port.dlls = if port.is_static {
+                    Vec::new()
+                } else {
+                    manifest.dlls
+                };
+                if !port.is_static {
+                    dynamic_ports.push(port_name.clone());
+                    for dll in &port.dlls {
+                        dll_owners.insert(dll.to_lowercase(), port_name.clone());
+                    }
+                }
+                for (name, path) in manifest.tools {
+                    tools.entry(name).or_insert(path);
+                }
+                if self.verify_installation {
+                    file_problems.insert(port_name.clone(), check_installed_files(&manifest.files));
+                }
+                headers.insert(port_name.clone(), manifest.headers);
+                share_files.insert(port_name.clone(), manifest.share_files);
+            }
+            file_warnings = try!(check_file_problems(&file_problems));
+
+            for port_name in &required_port_order {
+                let port = required_ports.get(port_name).unwrap();
+                let mut info = PortInfo {
+                    version: match &port.port_version[..] {
+                        "" | "0" => port.version.clone(),
+                        port_version => format!("{}#{}", port.version, port_version),
+                    },
+                    abi: if port.abi.is_empty() {
+                        None
+                    } else {
+                        Some(port.abi.clone())
+                    },
+                    deps: Vec::new(),
+                    license: None,
+                    homepage: None,
+                    installed_root: roots[installed.roots[port_name]]
+                        .installed_root()
+                        .to_path_buf(),
+                };
+                for dep in &port.deps {
+                    if required_ports.contains_key(dep) && !info.deps.contains(dep) {
+                        info.deps.push(dep.clone());
+                    }
+                }
+                // vcpkg writes an SPDX document for each port it builds
+                let spdx_file = Path::new("share").join(port_name).join("vcpkg.spdx.json");
+                if let Some(path) = share_files[port_name]
+                    .iter()
+                    .find(|f| f.ends_with(&spdx_file))
+                {
+                    read_spdx_file(path, port_name, &mut info);
+                }
+                port_info.insert(port_name.clone(), info);
+            }
+
+            for port_name in &required_port_order {
+                let port = required_ports.get(port_name).unwrap();
+                for lib in &port.libs {
+                    let stem = vcpkg_target
+                        .target_triplet
+                        .lib_stem_for(lib, port.is_static)
+                        .unwrap_or(lib);
+                    self.required_libs.push(stem.to_owned());
+                    self.lib_is_static.insert(stem.to_owned(), port.is_static);
                 }
+                self.port_dlls.extend(
+                    port.dlls
+                        .iter()
+                        .map(|s| vcpkg_target.target_triplet.dll_stem(s).to_owned()),
+                );
             }
         }
         // require explicit opt-in before using dynamically linked
@@ -1016,43 +2289,125 @@ impl Config {
             vcpkg_target.target_triplet.is_static,
             &vcpkg_target.target_triplet.triplet,
         );
-
-        if self.emit_includes {
-            lib.cargo_metadata.push(format!(
-                "cargo:include={}",
-                vcpkg_target.include_path.display()
-            ));
-        }
-        lib.include_paths.push(vcpkg_target.include_path.clone());
-
-        lib.cargo_metadata.push(format!(
-            "cargo:rustc-link-search=native={}",
-            vcpkg_target
-                .lib_path
-                .to_str()
-                .expect("failed to convert string type")
-        ));
-        lib.link_paths.push(vcpkg_target.lib_path.clone());
-        if !vcpkg_target.target_triplet.is_static {
-            lib.cargo_metadata.push(format!(
-                "cargo:rustc-link-search=native={}",
-                vcpkg_target
-                    .bin_path
-                    .to_str()
-                    .expect("failed to convert string type")
-            ));
-            // this path is dropped by recent versions of cargo hence the copies to OUT_DIR below
-            lib.dll_paths.push(vcpkg_target.bin_path.clone());
+        lib.cargo_metadata
+            .extend(vcpkg_target.triplet_warning.clone());
+        lib.cargo_metadata.extend(crt_warning);
+        lib.cargo_metadata.extend(conflicts);
+        lib.cargo_metadata.extend(file_warnings);
+
+        let needs_runtime = !vcpkg_target.target_triplet.is_static || !dynamic_ports.is_empty();
+        for &index in &used_roots {
+            self.emit_root_paths(&mut lib, &roots[index], needs_runtime);
         }
+        lib.dynamic_ports = dynamic_ports;
+        lib.tools = tools;
+        lib.headers = headers;
+        lib.share_files = share_files;
+        lib.port_info = port_info;
+        lib.file_problems = file_problems;
 
         lib.ports = required_port_order;
 
+        // these usually need the libraries of the port, so they go first
+        try!(self.emit_manual_link_libs(&mut lib, &vcpkg_target));
         try!(self.emit_libs(&mut lib, &vcpkg_target));
 
-        if self.copy_dlls {
+        if !vcpkg_target.target_triplet.shared_libs_in_lib_dir() {
+            add_imported_dlls(&mut lib, &vcpkg_target);
+
+            if self.copy_pdbs {
+                lib.found_pdbs = lib
+                    .found_dlls
+                    .iter()
+                    .map(|dll| dll.with_extension("pdb"))
+                    .filter(|pdb| pdb.exists())
+                    .collect();
+            }
+        }
+
+        if self.deployment_manifest && !lib.found_dlls.is_empty() {
+            try!(self.find_dll_owners(&mut lib, &vcpkg_target, dll_owners));
+        }
+
+        // shared libraries on Linux and macOS are loaded from the vcpkg tree
+        // through Library::rpaths, so copies of them would not be used
+        if self.copy_dlls && !vcpkg_target.target_triplet.shared_libs_in_lib_dir() {
             try!(self.do_dll_copy(&mut lib));
         }
 
+        if self.deployment_manifest {
+            match env::var_os("OUT_DIR") {
+                Some(out_dir) => {
+                    let path =
+                        Path::new(&out_dir).join(format!("vcpkg-deployment-{}.json", port_name));
+                    try!(File::create(&path)
+                        .and_then(|mut f| f.write_all(lib.deployment_manifest().as_bytes()))
+                        .map_err(|_| Error::VcpkgInstallation(format!(
+                            "Can't write deployment manifest {}",
+                            path.display()
+                        ))));
+                }
+                None => return Err(Error::LibNotFound("Unable to get OUT_DIR".to_owned())),
+            }
+        }
+
+        if self.copy_licenses {
+            match env::var_os("OUT_DIR") {
+                Some(out_dir) => {
+                    try!(lib.copy_licenses(&Path::new(&out_dir).join("licenses")));
+                }
+                None => return Err(Error::LibNotFound("Unable to get OUT_DIR".to_owned())),
+            }
+        }
+
+        if self.track_abi {
+            // rerun when vcpkg installs or removes anything, which it records here
+            for &index in &used_roots {
+                let status_path = &roots[index].status_path;
+                for path in &[status_path.join("status"), status_path.join("updates")] {
+                    if path.exists() {
+                        lib.cargo_metadata
+                            .push(format!("cargo:rerun-if-changed={}", path.display()));
+                    }
+                }
+            }
+            for (port, info) in &lib.port_info {
+                lib.cargo_metadata.push(format!(
+                    "cargo:rustc-env=VCPKG_ABI_{}={}",
+                    envify(port),
+                    info.abi.as_ref().unwrap_or(&info.version)
+                ));
+            }
+            let fingerprint = lib.abi_fingerprint();
+            lib.cargo_metadata
+                .push(format!("cargo:rustc-env=VCPKG_ABI={}", fingerprint));
+
+            match env::var_os("OUT_DIR") {
+                Some(out_dir) => {
+                    let path = Path::new(&out_dir).join(format!("vcpkg-abi-{}.txt", port_name));
+                    let contents = lib.abi_summary();
+                    // only written when it changes, so that anything tracking the
+                    // file is only rebuilt when it has to be
+                    let mut old = String::new();
+                    let unchanged = File::open(&path)
+                        .and_then(|mut f| f.read_to_string(&mut old))
+                        .map(|_| old == contents)
+                        .unwrap_or(false);
+                    if !unchanged {
+                        try!(File::create(&path)
+                            .and_then(|mut f| f.write_all(contents.as_bytes()))
+                            .map_err(|_| Error::VcpkgInstallation(format!(
+                                "Can't write ABI summary {}",
+                                path.display()
+                            ))));
+                    }
+                }
+                None => return Err(Error::LibNotFound("Unable to get OUT_DIR".to_owned())),
+            }
+        }
+
+        try!(record_resolved_ports(&mut lib));
+
         if self.cargo_metadata {
             for line in &lib.cargo_metadata {
                 println!("{}", line);
@@ -1074,13 +2429,143 @@ impl Config {
         self
     }
 
-    /// Should DLLs be copied to OUT_DIR?
+    /// Should DLLs be copied to OUT_DIR? The shared libraries of Linux and macOS
+    /// triplets are not copied, as they are found through `Library::rpaths` instead.
     /// Defaults to `true`.
     pub fn copy_dlls(&mut self, copy_dlls: bool) -> &mut Config {
         self.copy_dlls = copy_dlls;
         self
     }
 
+    /// Should DLLs also be copied to the directories that cargo puts binaries in,
+    /// `target/<profile>` for `cargo run` and `target/<profile>/deps` for `cargo test`,
+    /// so that dynamically linked programs can find them? The directories are
+    /// worked out from `OUT_DIR`. This has no effect if `copy_dlls` is `false`.
+    ///
+    /// This is opt-in, as it writes to directories that are shared by every crate in
+    /// the build. Without it, dynamically linked programs only run once the DLLs are
+    /// on the `PATH`, so set it for `cargo run` and `cargo test` to work out of the box.
+    /// Defaults to `false`.
+    pub fn copy_dlls_to_target_dir(&mut self, copy_dlls_to_target_dir: bool) -> &mut Config {
+        self.copy_dlls_to_target_dir = copy_dlls_to_target_dir;
+        self
+    }
+
+    /// Copy DLLs to `dir` as well as to `OUT_DIR`. This may be called more than
+    /// once to copy DLLs to several directories. This has no effect if `copy_dlls`
+    /// is `false`.
+    pub fn dll_destination<P: Into<PathBuf>>(&mut self, dir: P) -> &mut Config {
+        self.dll_destinations.push(dir.into());
+        self
+    }
+
+    /// Should the program databases (`.pdb` files) next to the DLLs be copied along
+    /// with the DLLs, so that there are symbols for the native code in the debugger?
+    /// Only the PDBs with the same name as a DLL that was found are copied.
+    /// Defaults to `false`.
+    pub fn copy_pdbs(&mut self, copy_pdbs: bool) -> &mut Config {
+        self.copy_pdbs = copy_pdbs;
+        self
+    }
+
+    /// Copy DLLs, or create hard or symbolic links to them in the vcpkg tree.
+    /// DLLs that are already in place are left alone, so that an unchanged DLL is
+    /// not rewritten on every build.
+    /// Defaults to `DllCopyMode::Copy`.
+    pub fn dll_copy_mode(&mut self, dll_copy_mode: DllCopyMode) -> &mut Config {
+        self.dll_copy_mode = dll_copy_mode;
+        self
+    }
+
+    /// Should a deployment manifest be written to `OUT_DIR/vcpkg-deployment-<package>.json`,
+    /// listing the DLLs and PDBs that programs using the package need at runtime?
+    /// See `Library::deployment_manifest`.
+    /// Defaults to `false`.
+    pub fn deployment_manifest(&mut self, deployment_manifest: bool) -> &mut Config {
+        self.deployment_manifest = deployment_manifest;
+        self
+    }
+
+    /// Should the copyright files of all of the ports that are linked be copied
+    /// to `OUT_DIR/licenses`, along with a `licenses.txt` manifest? See
+    /// `Library::copy_licenses`.
+    /// Defaults to `false`.
+    pub fn copy_licenses(&mut self, copy_licenses: bool) -> &mut Config {
+        self.copy_licenses = copy_licenses;
+        self
+    }
+
+    /// Should the ABI hash vcpkg records for each port be passed to rustc, so that
+    /// the crate is rebuilt when a port is rebuilt, even if its version stays the same?
+    ///
+    /// This emits `cargo:rustc-env=VCPKG_ABI_<PORT>=<hash>` for each port, e.g.
+    /// `VCPKG_ABI_LIBPNG`, and `cargo:rustc-env=VCPKG_ABI=<fingerprint>` with
+    /// `Library::abi_fingerprint`. The version of the port is used for ports that have
+    /// no ABI hash. The same is written to `OUT_DIR/vcpkg-abi-<package>.txt`, which is
+    /// only rewritten when it changes. `cargo:rerun-if-changed` lines are emitted for
+    /// the vcpkg status database so that the build script runs again when vcpkg
+    /// installs something, which also stops cargo rerunning the build script when any
+    /// other file in the package changes.
+    /// Defaults to `false`.
+    pub fn track_abi(&mut self, track_abi: bool) -> &mut Config {
+        self.track_abi = track_abi;
+        self
+    }
+
+    /// Should `find_package` check that every file the `.list` files of the ports
+    /// name is installed and not empty, rather than only the libraries it links to?
+    /// If any are missing, `find_package` fails with a list of them, and empty files,
+    /// which some ports install on purpose, are warnings. This catches vcpkg
+    /// trees that were partially deleted or restored from a cache, at the cost of
+    /// reading the metadata of every installed file. See also `Config::verify`.
+    /// Defaults to `false`.
+    pub fn verify_installation(&mut self, verify_installation: bool) -> &mut Config {
+        self.verify_installation = verify_installation;
+        self
+    }
+
+    /// Check the installed files of the package `port_name` and all of the ports it
+    /// depends on against their `.list` files, without emitting any metadata.
+    ///
+    /// Returns the problems found for each of the ports, which are empty for the
+    /// ports that are installed correctly.
+    pub fn verify(&mut self, port_name: &str) -> Result<BTreeMap<String, Vec<FileProblem>>, Error> {
+        let msvc_target = try!(self.get_target_triplet());
+        let vcpkg_target = try!(self.select_vcpkg_target(port_name, msvc_target));
+        let roots = vcpkg_target.roots();
+        let installed = try!(load_installed_ports(&roots, self.persistent_cache));
+        if !installed.ports.contains_key(port_name) {
+            return Err(Error::LibNotFound(format!(
+                "package {} is not installed for vcpkg triplet {}",
+                port_name, vcpkg_target.target_triplet.triplet
+            )));
+        }
+
+        let mut file_problems = BTreeMap::new();
+        for port_name in port_closure(&installed.ports, port_name) {
+            let root = &roots[installed.roots[&port_name]];
+            let manifest = try!(load_port_manifest(
+                &root.status_path,
+                &port_name,
+                &installed.ports[&port_name].version,
+                root
+            ));
+            file_problems.insert(port_name, check_installed_files(&manifest.files));
+        }
+        Ok(file_problems)
+    }
+
+    /// Cache the parsed vcpkg status database in `OUT_DIR` so that it survives
+    /// between runs of the build script. Defaults to `false`.
+    ///
+    /// Repeated lookups within a single build script are always served from an
+    /// in-process cache. The cache is invalidated if the vcpkg status file or any of
+    /// the incremental updates to it change.
+    pub fn persistent_cache(&mut self, persistent_cache: bool) -> &mut Config {
+        self.persistent_cache = persistent_cache;
+        self
+    }
+
     /// Specify vcpkg installed directory. This is useful for manifest mode and custom vcpkg installation.
     /// If not set, will use VCPKG_INSTALLED_ROOT environment variable.
     /// If VCPKG_INSTALLED_ROOT is not set, will use VCPKG_ROOT/installed.
@@ -1089,6 +2574,19 @@ impl Config {
         self
     }
 
+    /// Also look for ports in another installed directory, such as one holding ports
+    /// that are built separately from the main vcpkg tree.
+    ///
+    /// This may be called more than once. The installed directories are searched in
+    /// the order they are added and before the main installed directory, and the
+    /// first one that has a port is the one it is used from. A port that is also
+    /// installed in a later directory is reported as a cargo warning. If this is not
+    /// called, the directories in `VCPKGRS_OVERLAY_INSTALLED_ROOTS` are used.
+    pub fn overlay_installed_root<P: Into<PathBuf>>(&mut self, root: P) -> &mut Config {
+        self.overlay_installed_roots.push(root.into());
+        self
+    }
+
     /// Define which path to use as vcpkg root overriding the VCPKG_ROOT environment variable
     /// Default to `None`, which means use VCPKG_ROOT or try to find out automatically
     pub fn vcpkg_root(&mut self, vcpkg_root: PathBuf) -> &mut Config {
@@ -1101,12 +2599,105 @@ impl Config {
     /// Specifying a triplet using `target_triplet` will override the default triplet for this crate. This
     /// cannot change the choice of triplet made by other crates, so a safer choice will be to set
     /// `VCPKGRS_TRIPLET` in the environment which will allow all crates to use a consistent set of
-    /// external dependencies.
+    /// external dependencies. Crates that end up linking a port from different triplets are
+    /// reported as described for `VCPKGRS_CONSISTENCY` in the module docs.
     pub fn target_triplet<S: AsRef<str>>(&mut self, triplet: S) -> &mut Config {
         self.target = Some(triplet.into());
         self
     }
 
+    /// Should a triplet that links the C runtime differently to the Rust target be
+    /// an error, `Error::CrtMismatch`, rather than a `cargo:warning`? Mixing them
+    /// usually fails to link with LNK2038 errors, but can work for C libraries that
+    /// don't pass C runtime objects such as `FILE *` across their API.
+    /// Defaults to `false`.
+    pub fn crt_mismatch_is_error(&mut self, crt_mismatch_is_error: bool) -> &mut Config {
+        self.crt_mismatch_is_error = crt_mismatch_is_error;
+        self
+    }
+
+    /// Specify triplets to try, in order, if the package is not installed for the
+    /// selected triplet, for example
+    /// `.triplet_candidates(&["x64-windows-static", "x64-windows-custom"])`.
+    ///
+    /// The first triplet that has the package installed and is compatible with the Rust
+    /// target is used. Triplets for another operating system or architecture, triplets
+    /// that link the C runtime differently, and dynamic triplets when `VCPKGRS_DYNAMIC`
+    /// is not set, are skipped. If none is found, the error lists
+    /// the triplets that were tried. This overrides the `VCPKGRS_TRIPLET_CANDIDATES`
+    /// environment variable.
+    pub fn triplet_candidates<S: AsRef<str>>(&mut self, triplets: &[S]) -> &mut Config {
+        self.triplet_candidates = triplets.iter().map(|t| t.into()).collect();
+        self
+    }
+
+    /// Find the executable `tool_name` provided by the package `port_name`, for
+    /// running code generators such as `protoc` from a build script.
+    ///
+    /// Tools have to run on the machine doing the build, so they are looked up in
+    /// `installed/<host triplet>/tools/<port>/` rather than in the triplet used for
+    /// linking. The host triplet is `VCPKG_DEFAULT_HOST_TRIPLET` if that is set, as it
+    /// is for `vcpkg install`, or else is derived from `HOST`, e.g. `x64-windows` or
+    /// `x64-linux`.
+    ///
+    /// ```no_run
+    /// let protoc = vcpkg::Config::new().find_tool("protobuf", "protoc").unwrap();
+    /// ```
+    pub fn find_tool(&mut self, port_name: &str, tool_name: &str) -> Result<PathBuf, Error> {
+        // bail out if requested to not try at all
+        if env::var_os("VCPKGRS_DISABLE").is_some() {
+            return Err(Error::DisabledByEnv("VCPKGRS_DISABLE".to_owned()));
+        }
+
+        let host_triplet = try!(detect_host_triplet());
+        let vcpkg_target = try!(find_vcpkg_target(&self, &host_triplet));
+        let roots = vcpkg_target.roots();
+        let installed = try!(load_installed_ports(&roots, self.persistent_cache));
+
+        let port = match installed.ports.get(port_name) {
+            Some(port) => port,
+            None => {
+                return Err(Error::LibNotFound(format!(
+                    "package {} is not installed for vcpkg host triplet {}",
+                    port_name, vcpkg_target.target_triplet.triplet
+                )))
+            }
+        };
+
+        let root = &roots[installed.roots[port_name]];
+        let manifest = try!(load_port_manifest(
+            &root.status_path,
+            port_name,
+            &port.version,
+            root
+        ));
+
+        match manifest
+            .tools
+            .into_iter()
+            .find(|&(ref name, _)| name == tool_name)
+        {
+            Some((_, path)) => {
+                if !path.exists() {
+                    return Err(Error::VcpkgInstallation(format!(
+                        "tool {} is listed in the manifest of package {} but {} does not exist",
+                        tool_name,
+                        port_name,
+                        path.display()
+                    )));
+                }
+                if self.cargo_metadata {
+                    println!("cargo:rerun-if-changed={}", path.display());
+                }
+                Ok(path)
+            }
+            None => Err(Error::LibNotFound(format!(
+                "package {} for vcpkg host triplet {} does not provide the tool {}",
+                port_name, vcpkg_target.target_triplet.triplet, tool_name
+            ))),
+        }
+    }
+
     /// Find the library `port_name` in a Vcpkg tree.
     ///
     /// This will use all configuration previously set to select the
@@ -1148,6 +2739,7 @@ impl Config {
         }
 
         let vcpkg_target = try!(find_vcpkg_target(&self, &msvc_target));
+        let crt_warning = try!(self.check_crt_linkage(&vcpkg_target));
 
         // require explicit opt-in before using dynamically linked
         // variants, otherwise cargo install of various things will
@@ -1160,50 +2752,65 @@ impl Config {
             vcpkg_target.target_triplet.is_static,
             &vcpkg_target.target_triplet.triplet,
         );
+        lib.cargo_metadata
+            .extend(vcpkg_target.triplet_warning.clone());
+        lib.cargo_metadata.extend(crt_warning);
+
+        for root in &vcpkg_target.roots() {
+            self.emit_root_paths(&mut lib, root, !vcpkg_target.target_triplet.is_static);
+        }
+
+        try!(self.emit_libs(&mut lib, &vcpkg_target));
+
+        // shared libraries on Linux and macOS are loaded from the vcpkg tree
+        // through Library::rpaths, so copies of them would not be used
+        if self.copy_dlls && !vcpkg_target.target_triplet.shared_libs_in_lib_dir() {
+            try!(self.do_dll_copy(&mut lib));
+        }
+
+        if self.cargo_metadata {
+            for line in &lib.cargo_metadata {
+                println!("{}", line);
+            }
+        }
+        Ok(lib)
+    }
 
+    // Add the include, library and runtime paths of one installed directory.
+    fn emit_root_paths(&self, lib: &mut Library, root: &VcpkgTarget, needs_runtime: bool) {
         if self.emit_includes {
-            lib.cargo_metadata.push(format!(
-                "cargo:include={}",
-                vcpkg_target.include_path.display()
-            ));
+            lib.cargo_metadata
+                .push(format!("cargo:include={}", root.include_path.display()));
         }
-        lib.include_paths.push(vcpkg_target.include_path.clone());
+        lib.include_paths.push(root.include_path.clone());
 
         lib.cargo_metadata.push(format!(
             "cargo:rustc-link-search=native={}",
-            vcpkg_target
-                .lib_path
+            root.lib_path
                 .to_str()
                 .expect("failed to convert string type")
         ));
-        lib.link_paths.push(vcpkg_target.lib_path.clone());
-        if !vcpkg_target.target_triplet.is_static {
+        lib.link_paths.push(root.lib_path.clone());
+        if !needs_runtime {
+            // nothing is needed at runtime
+        } else if root.target_triplet.shared_libs_in_lib_dir() {
+            lib.dll_paths.push(root.lib_path.clone());
+            lib.rpaths.push(root.lib_path.clone());
+        } else {
             lib.cargo_metadata.push(format!(
                 "cargo:rustc-link-search=native={}",
-                vcpkg_target
-                    .bin_path
+                root.bin_path
                     .to_str()
                     .expect("failed to convert string type")
             ));
             // this path is dropped by recent versions of cargo hence the copies to OUT_DIR below
-            lib.dll_paths.push(vcpkg_target.bin_path.clone());
-        }
-
-        try!(self.emit_libs(&mut lib, &vcpkg_target));
-
-        if self.copy_dlls {
-            try!(self.do_dll_copy(&mut lib));
+            lib.dll_paths.push(root.bin_path.clone());
         }
-
-        if self.cargo_metadata {
-            for line in &lib.cargo_metadata {
-                println!("{}", line);
-            }
-        }
-        Ok(lib)
     }
 
     fn emit_libs(&mut self, lib: &mut Library, vcpkg_target: &VcpkgTarget) -> Result<(), Error> {
+        let roots = vcpkg_target.roots();
+        let mut all_import_libs_read = true;
         for required_lib in &self.required_libs {
             // this could use static-nobundle= for static libraries but it is apparently
             // not necessary to make the distinction for windows-msvc.
@@ -1213,55 +2820,188 @@ impl Config {
                 false => required_lib,
             };
 
-            lib.cargo_metadata
-                .push(format!("cargo:rustc-link-lib={}", link_name));
+            // the linkage of individual ports can be overridden by the triplet
+            let is_static = self
+                .lib_is_static
+                .get(required_lib)
+                .cloned()
+                .unwrap_or(vcpkg_target.target_triplet.is_static);
 
-            lib.found_names.push(String::from(link_name));
+            if !is_static && vcpkg_target.target_triplet.shared_libs_in_lib_dir() {
+                lib.cargo_metadata
+                    .push(format!("cargo:rustc-link-lib=dylib={}", link_name));
+            } else {
+                lib.cargo_metadata
+                    .push(format!("cargo:rustc-link-lib={}", link_name));
+            }
 
-            // verify that the library exists
-            let mut lib_location = vcpkg_target.lib_path.clone();
-            lib_location.push(required_lib.clone() + "." + &vcpkg_target.target_triplet.lib_suffix);
+            lib.found_names.push(String::from(link_name));
 
-            if !lib_location.exists() {
-                return Err(Error::LibNotFound(lib_location.display().to_string()));
+            // verify that the library exists, in the same order as the linker searches
+            let lib_file =
+                required_lib.clone() + "." + vcpkg_target.target_triplet.lib_suffix_for(is_static);
+            let (root, lib_location) = match roots
+                .iter()
+                .map(|root| (root, root.lib_path.join(&lib_file)))
+                .find(|&(_, ref path)| path.exists())
+            {
+                Some(found) => found,
+                None => {
+                    return Err(Error::LibNotFound(
+                        vcpkg_target.lib_path.join(&lib_file).display().to_string(),
+                    ))
+                }
+            };
+            if !is_static && !vcpkg_target.target_triplet.shared_libs_in_lib_dir() {
+                match import_library_dll(&lib_location) {
+                    Some(dll) => {
+                        lib.lib_dlls
+                            .insert(lib_location.clone(), root.bin_path.join(dll));
+                    }
+                    None => all_import_libs_read = false,
+                }
             }
             lib.found_libs.push(lib_location);
         }
 
-        if !vcpkg_target.target_triplet.is_static {
-            for required_dll in &self.required_dlls {
-                let mut dll_location = vcpkg_target.bin_path.clone();
-                dll_location.push(required_dll.clone() + ".dll");
+        if !vcpkg_target.target_triplet.is_static || !lib.dynamic_ports.is_empty() {
+            let mut required_dlls = self.required_dlls.clone();
+            if all_import_libs_read && !lib.lib_dlls.is_empty() {
+                // only the DLLs that are linked to are needed, rather than every
+                // DLL that the ports install
+                for (import_lib, dll_location) in &lib.lib_dlls {
+                    if !dll_location.exists() {
+                        return Err(Error::LibNotFound(format!(
+                            "{} is imported by {} but is not installed",
+                            dll_location.display(),
+                            import_lib.display()
+                        )));
+                    }
+                    if !lib.found_dlls.contains(dll_location) {
+                        lib.found_dlls.push(dll_location.clone());
+                    }
+                }
+            } else {
+                required_dlls.extend(self.port_dlls.iter().cloned());
+            }
+
+            for required_dll in &required_dlls {
+                let dll_location = |root: &VcpkgTarget| {
+                    if root.target_triplet.shared_libs_in_lib_dir() {
+                        root.lib_path.join(required_dll)
+                    } else {
+                        root.bin_path.join(required_dll.clone() + ".dll")
+                    }
+                };
 
                 // verify that the DLL exists
-                if !dll_location.exists() {
-                    return Err(Error::LibNotFound(dll_location.display().to_string()));
+                match roots.iter().map(&dll_location).find(|path| path.exists()) {
+                    Some(ref path) if lib.found_dlls.contains(path) => {}
+                    Some(path) => lib.found_dlls.push(path),
+                    None => {
+                        return Err(Error::LibNotFound(
+                            dll_location(vcpkg_target).display().to_string(),
+                        ))
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Work out which port installed each of the DLLs. DLLs that were found by
+    // following imports can belong to ports that are not in the dependency
+    // graph, so the manifests of the other ports are searched for those.
+    fn find_dll_owners(
+        &self,
+        lib: &mut Library,
+        vcpkg_target: &VcpkgTarget,
+        mut owners: BTreeMap<String, String>,
+    ) -> Result<(), Error> {
+        let file_name = |dll: &PathBuf| {
+            dll.file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or("")
+                .to_lowercase()
+        };
+
+        if lib
+            .found_dlls
+            .iter()
+            .any(|dll| !owners.contains_key(&file_name(dll)))
+        {
+            let roots = vcpkg_target.roots();
+            let installed = try!(load_installed_ports(&roots, self.persistent_cache));
+            for (port_name, port) in &installed.ports {
+                if lib.ports.contains(port_name) {
+                    continue;
+                }
+                // a broken port that is not needed should not fail the build
+                let root = &roots[installed.roots[port_name]];
+                if let Ok(manifest) =
+                    load_port_manifest(&root.status_path, port_name, &port.version, root)
+                {
+                    for dll in &manifest.dlls {
+                        owners
+                            .entry(dll.to_lowercase())
+                            .or_insert(port_name.clone());
+                    }
                 }
-                lib.found_dlls.push(dll_location);
             }
         }
 
+        for dll in &lib.found_dlls {
+            if let Some(port) = owners.get(&file_name(dll)) {
+                lib.dll_ports.insert(dll.clone(), port.clone());
+            }
+        }
         Ok(())
     }
 
     fn do_dll_copy(&mut self, lib: &mut Library) -> Result<(), Error> {
         if let Some(target_dir) = env::var_os("OUT_DIR") {
             if !lib.found_dlls.is_empty() {
-                for file in &lib.found_dlls {
-                    let mut dest_path = Path::new(target_dir.as_os_str()).to_path_buf();
-                    dest_path.push(Path::new(file.file_name().unwrap()));
+                let mut dest_dirs = vec![PathBuf::from(&target_dir)];
+                if self.copy_dlls_to_target_dir {
+                    dest_dirs.extend(binary_dirs_from_out_dir(Path::new(&target_dir)));
+                }
+                dest_dirs.extend(self.dll_destinations.iter().cloned());
+
+                for dest_dir in &dest_dirs {
                     try!(
-                        fs::copy(file, &dest_path).map_err(|_| Error::LibNotFound(format!(
-                            "Can't copy file {} to {}",
-                            file.to_string_lossy(),
-                            dest_path.to_string_lossy()
+                        fs::create_dir_all(dest_dir).map_err(|_| Error::LibNotFound(format!(
+                            "Can't create directory {}",
+                            dest_dir.display()
                         )))
                     );
-                    println!(
-                        "vcpkg build helper copied {} to {}",
-                        file.to_string_lossy(),
-                        dest_path.to_string_lossy()
-                    );
+                    for file in lib.found_dlls.iter().chain(&lib.found_pdbs) {
+                        let dest_path = dest_dir.join(file.file_name().unwrap());
+                        match deploy_file(file, &dest_path, self.dll_copy_mode) {
+                            Ok(true) => println!(
+                                "vcpkg build helper copied {} to {}",
+                                file.to_string_lossy(),
+                                dest_path.to_string_lossy()
+                            ),
+                            Ok(false) => {}
+                            // the DLL is probably in use by a program that is still
+                            // running, which should not stop the build
+                            Err(ref e) if dest_path.exists() => lib.cargo_metadata.push(format!(
+                                "cargo:warning=Can't replace {} with {}, keeping the existing \
+                                 file: {}",
+                                dest_path.display(),
+                                file.display(),
+                                e
+                            )),
+                            Err(_) => {
+                                return Err(Error::LibNotFound(format!(
+                                    "Can't copy file {} to {}",
+                                    file.to_string_lossy(),
+                                    dest_path.to_string_lossy()
+                                )))
+                            }
+                        }
+                    }
                 }
                 lib.cargo_metadata.push(format!(
                     "cargo:rustc-link-search=native={}",
@@ -1279,7 +3019,69 @@ impl Config {
         Ok(())
     }
 
-    /// Override the name of the library to look for if it differs from the package name.
+    /// Also link to libraries in `lib/manual-link`, such as `SDL2main` or `gtest_main`.
+    ///
+    /// vcpkg puts libraries that most programs should not link to automatically into
+    /// `lib/manual-link`, so `find_package` does not link to them unless asked to. The
+    /// names are library names without the extension, e.g.
+    /// `.manual_link(&["SDL2main"])` for `SDL2main.lib` or `libSDL2main.a`. As with
+    /// the other libraries, the release build is linked, so a library that is only in
+    /// `debug/lib/manual-link` is not found.
+    pub fn manual_link<S: AsRef<str>>(&mut self, libs: &[S]) -> &mut Config {
+        self.manual_link_libs
+            .extend(libs.iter().map(|l| l.as_ref().to_owned()));
+        self
+    }
+
+    fn emit_manual_link_libs(
+        &self,
+        lib: &mut Library,
+        vcpkg_target: &VcpkgTarget,
+    ) -> Result<(), Error> {
+        let triplet = &vcpkg_target.target_triplet;
+        // the release libraries are linked, so debug/lib/manual-link is not searched
+        let dirs: Vec<PathBuf> = vcpkg_target
+            .roots()
+            .iter()
+            .map(|root| root.lib_path.join("manual-link"))
+            .collect();
+        for name in &self.manual_link_libs {
+            let mut file_names = vec![format!("{}.{}", name, triplet.lib_suffix_for(true))];
+            if triplet.strip_lib_prefix {
+                file_names.insert(0, format!("lib{}.{}", name, triplet.lib_suffix_for(true)));
+            }
+            let found = dirs
+                .iter()
+                .flat_map(|dir| file_names.iter().map(move |f| dir.join(f)))
+                .find(|path| path.exists());
+            let lib_location = match found {
+                Some(path) => path,
+                None => {
+                    return Err(Error::LibNotFound(format!(
+                        "manual-link library {} is not in {}",
+                        name,
+                        vcpkg_target.lib_path.join("manual-link").display()
+                    )))
+                }
+            };
+
+            let dir = lib_location.parent().unwrap().to_path_buf();
+            if !lib.link_paths.contains(&dir) {
+                lib.cargo_metadata.push(format!(
+                    "cargo:rustc-link-search=native={}",
+                    dir.to_str().expect("failed to convert string type")
+                ));
+                lib.link_paths.push(dir);
+            }
+            lib.cargo_metadata
+                .push(format!("cargo:rustc-link-lib={}", name));
+            lib.found_names.push(name.clone());
+            lib.found_libs.push(lib_location);
+        }
+        Ok(())
+    }
+
+    /// Override the name of the library to look for if it differs from the package name.
     ///
     /// It should not be necessary to use `lib_name` anymore. Calling `find_package` with a package name
     /// will result in the correct library names.
@@ -1310,6 +3112,87 @@ impl Config {
     }
 }
 
+fn check_installed_files(files: &[PathBuf]) -> Vec<FileProblem> {
+    let mut problems = Vec::new();
+    for file in files {
+        // metadata follows symlinks, so a dangling symlink counts as missing
+        match fs::metadata(file) {
+            Ok(ref metadata) if metadata.is_file() && metadata.len() == 0 => {
+                problems.push(FileProblem::Empty(file.clone()))
+            }
+            Ok(_) => {}
+            Err(_) => problems.push(FileProblem::Missing(file.clone())),
+        }
+    }
+    problems
+}
+
+// Fail if any of the files are missing. Empty files are only reported, since some
+// ports install them on purpose, so the warnings for them are returned.
+fn check_file_problems(
+    file_problems: &BTreeMap<String, Vec<FileProblem>>,
+) -> Result<Vec<String>, Error> {
+    // a tree that has been wiped can have many thousands of problems
+    const MAX_LISTED: usize = 10;
+
+    let mut count = 0;
+    let mut listed = Vec::new();
+    let mut warnings = Vec::new();
+    for (port, problems) in file_problems {
+        for problem in problems {
+            match *problem {
+                FileProblem::Missing(_) => {
+                    count += 1;
+                    if listed.len() < MAX_LISTED {
+                        listed.push(format!("{}: {}", port, problem));
+                    }
+                }
+                FileProblem::Empty(_) => {
+                    warnings.push(format!("cargo:warning={}: {}", port, problem));
+                }
+            }
+        }
+    }
+    if count == 0 {
+        return Ok(warnings);
+    }
+    if count > listed.len() {
+        let more = count - listed.len();
+        listed.push(format!("and {} more", more));
+    }
+    Err(Error::VcpkgInstallation(format!(
+        "the vcpkg tree is damaged, reinstall the ports to fix it: {}",
+        listed.join(", ")
+    )))
+}
+
+// The port and all of the ports that it depends on, directly or indirectly, in
+// link order.
+fn port_closure(ports: &BTreeMap<String, Port>, port_name: &str) -> Vec<String> {
+    let mut required_port_order = Vec::new();
+    // working of ports that we need to include
+    let mut ports_to_scan = vec![port_name.to_owned()];
+    let mut seen = Vec::new();
+
+    while !ports_to_scan.is_empty() {
+        let port_name = ports_to_scan.pop().unwrap();
+
+        if seen.contains(&port_name) {
+            continue;
+        }
+
+        if let Some(port) = ports.get(&port_name) {
+            for dep in &port.deps {
+                ports_to_scan.push(dep.clone());
+            }
+            seen.push(port_name.clone());
+            remove_item(&mut required_port_order, &port_name);
+            required_port_order.push(port_name);
+        }
+    }
+    required_port_order
+}
+
 fn remove_item(cont: &mut Vec<String>, item: &String) -> Option<String> {
     match cont.iter().position(|x| *x == *item) {
         Some(pos) => Some(cont.remove(pos)),
@@ -1325,13 +3208,222 @@ impl Library {
             include_paths: Vec::new(),
             cargo_metadata: Vec::new(),
             is_static: is_static,
+            rpaths: Vec::new(),
             found_dlls: Vec::new(),
+            found_pdbs: Vec::new(),
+            dll_ports: BTreeMap::new(),
             found_libs: Vec::new(),
             found_names: Vec::new(),
+            lib_dlls: BTreeMap::new(),
+            unresolved_dlls: Vec::new(),
+            file_problems: BTreeMap::new(),
             ports: Vec::new(),
+            dynamic_ports: Vec::new(),
+            tools: BTreeMap::new(),
+            headers: BTreeMap::new(),
+            share_files: BTreeMap::new(),
+            port_info: BTreeMap::new(),
             vcpkg_triplet: vcpkg_triplet.to_string(),
         }
     }
+
+    /// The copyright file of each port, `share/<port>/copyright`, which vcpkg
+    /// installs for every port. Ports without one are left out.
+    pub fn copyright_files(&self) -> BTreeMap<String, PathBuf> {
+        let mut files = BTreeMap::new();
+        for (port, share_files) in &self.share_files {
+            let copyright = Path::new("share").join(port).join("copyright");
+            if let Some(file) = share_files.iter().find(|f| f.ends_with(&copyright)) {
+                files.insert(port.clone(), file.clone());
+            }
+        }
+        files
+    }
+
+    /// A SHA-256 hash, in hex, of the triplet and of the version and ABI hash of each
+    /// of the ports. It changes whenever any of the ports is rebuilt differently, such
+    /// as with other features or by a newer compiler, even if the versions do not.
+    pub fn abi_fingerprint(&self) -> String {
+        sha256(self.abi_summary().as_bytes())
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    // A line for the triplet and one for each port with its version and ABI hash.
+    fn abi_summary(&self) -> String {
+        let mut summary = format!("triplet\t{}\n", self.vcpkg_triplet);
+        for (port, info) in &self.port_info {
+            summary.push_str(&format!(
+                "{}\t{}\t{}\n",
+                port,
+                info.version,
+                info.abi.as_ref().map(|a| &a[..]).unwrap_or("-")
+            ));
+        }
+        summary
+    }
+
+    /// A [CycloneDX](https://cyclonedx.org/) JSON document listing the ports that are
+    /// linked, with their versions, triplet, ABI hashes, licenses and the dependencies
+    /// between them, so that a software bill of materials can include the native
+    /// libraries found by vcpkg.
+    ///
+    /// Each port is a component with a `bom-ref` of `vcpkg:<port>:<triplet>`, which
+    /// can be merged into the SBOM of the crate.
+    pub fn sbom_json(&self) -> String {
+        let bom_ref = |port: &str| format!("vcpkg:{}:{}", port, self.vcpkg_triplet);
+
+        let mut components = Vec::new();
+        let mut dependencies = Vec::new();
+        for port in &self.ports {
+            let info = match self.port_info.get(port) {
+                Some(info) => info,
+                None => continue,
+            };
+            let mut component = format!(
+                "{{\"type\": \"library\", \"bom-ref\": {}, \"name\": {}, \"version\": {}",
+                json_string(&bom_ref(port)),
+                json_string(port),
+                json_string(&info.version)
+            );
+            if let Some(ref license) = info.license {
+                component.push_str(&format!(
+                    ", \"licenses\": [{{\"expression\": {}}}]",
+                    json_string(license)
+                ));
+            }
+            if let Some(ref homepage) = info.homepage {
+                component.push_str(&format!(
+                    ", \"externalReferences\": [{{\"type\": \"website\", \"url\": {}}}]",
+                    json_string(homepage)
+                ));
+            }
+            let mut properties = vec![format!(
+                "{{\"name\": \"vcpkg:triplet\", \"value\": {}}}",
+                json_string(&self.vcpkg_triplet)
+            )];
+            if let Some(ref abi) = info.abi {
+                properties.push(format!(
+                    "{{\"name\": \"vcpkg:abi\", \"value\": {}}}",
+                    json_string(abi)
+                ));
+            }
+            component.push_str(&format!(", \"properties\": [{}]}}", properties.join(", ")));
+            components.push(component);
+
+            let deps: Vec<String> = info.deps.iter().map(|d| json_string(&bom_ref(d))).collect();
+            dependencies.push(format!(
+                "{{\"ref\": {}, \"dependsOn\": [{}]}}",
+                json_string(&bom_ref(port)),
+                deps.join(", ")
+            ));
+        }
+
+        format!(
+            "{{\n  \"bomFormat\": \"CycloneDX\",\n  \"specVersion\": \"1.5\",\n  \"version\": 1,\n  \
+             \"components\": [\n    {}\n  ],\n  \"dependencies\": [\n    {}\n  ]\n}}\n",
+            components.join(",\n    "),
+            dependencies.join(",\n    ")
+        )
+    }
+
+    /// A JSON document describing the files that programs linked to the libraries need
+    /// at runtime, i.e. `found_dlls` and `found_pdbs`, for packaging them with
+    /// an installer. Each file has its name, the path it was found at, the port
+    /// that installed it (or `null` if that is not known) and its SHA-256 checksum.
+    pub fn deployment_manifest(&self) -> String {
+        let mut files = Vec::new();
+        for file in self.found_dlls.iter().chain(&self.found_pdbs) {
+            // PDBs belong to the port of the DLL next to them
+            let owner = self
+                .dll_ports
+                .get(&file.with_extension("dll"))
+                .map(|p| json_string(p))
+                .unwrap_or("null".to_owned());
+            files.push(format!(
+                "{{\"file\": {}, \"source\": {}, \"port\": {}, \"sha256\": {}}}",
+                json_string(&file.file_name().unwrap_or(OsStr::new("")).to_string_lossy()),
+                json_string(&file.to_string_lossy()),
+                owner,
+                sha256_file(file)
+                    .map(|s| json_string(&s))
+                    .unwrap_or("null".to_owned())
+            ));
+        }
+        format!(
+            "{{\n  \"triplet\": {},\n  \"files\": [\n    {}\n  ]\n}}\n",
+            json_string(&self.vcpkg_triplet),
+            files.join(",\n    ")
+        )
+    }
+
+    /// Copy the files that programs linked to the libraries need at runtime, i.e.
+    /// `found_dlls` and `found_pdbs`, to `dir`. Files that are already there and
+    /// unchanged are not copied again.
+    ///
+    /// Returns the paths of the files in `dir`.
+    pub fn deploy(&self, dir: &Path) -> Result<Vec<PathBuf>, Error> {
+        try!(
+            fs::create_dir_all(dir).map_err(|_| Error::VcpkgInstallation(format!(
+                "Can't create directory {}",
+                dir.display()
+            )))
+        );
+        let mut deployed = Vec::new();
+        for file in self.found_dlls.iter().chain(&self.found_pdbs) {
+            let dest_path = dir.join(file.file_name().unwrap());
+            try!(
+                deploy_file(file, &dest_path, DllCopyMode::Copy).map_err(|e| {
+                    Error::VcpkgInstallation(format!(
+                        "Can't copy file {} to {}: {}",
+                        file.display(),
+                        dest_path.display(),
+                        e
+                    ))
+                })
+            );
+            deployed.push(dest_path);
+        }
+        Ok(deployed)
+    }
+
+    /// Copy the copyright file of each port to `<dir>/<port>/copyright`, and
+    /// write a manifest to `<dir>/licenses.txt` with a line for each port giving the
+    /// port name and the copied file, or `none` if the port has no copyright file.
+    ///
+    /// Returns the path of the manifest.
+    pub fn copy_licenses(&self, dir: &Path) -> Result<PathBuf, Error> {
+        let copyright_files = self.copyright_files();
+        let mut manifest = String::new();
+        for port in &self.ports {
+            match copyright_files.get(port) {
+                Some(file) => {
+                    let dest_dir = dir.join(port);
+                    let dest_path = dest_dir.join("copyright");
+                    try!(fs::create_dir_all(&dest_dir)
+                        .and_then(|_| fs::copy(file, &dest_path))
+                        .map_err(|_| Error::VcpkgInstallation(format!(
+                            "Can't copy file {} to {}",
+                            file.display(),
+                            dest_path.display()
+                        ))));
+                    manifest.push_str(&format!("{}\t{}/copyright\n", port, port));
+                }
+                None => manifest.push_str(&format!("{}\tnone\n", port)),
+            }
+        }
+
+        let manifest_path = dir.join("licenses.txt");
+        try!(fs::create_dir_all(dir)
+            .and_then(|_| File::create(&manifest_path))
+            .and_then(|mut f| f.write_all(manifest.as_bytes()))
+            .map_err(|_| Error::VcpkgInstallation(format!(
+                "Can't write license manifest {}",
+                manifest_path.display()
+            ))));
+        Ok(manifest_path)
+    }
 }
 
 fn envify(name: &str) -> String {
@@ -1341,185 +3433,1280 @@ fn envify(name: &str) -> String {
         .collect()
 }
 
+/// The parts of a Rust target that are used to select a vcpkg triplet,
+/// using the same values as the `target_arch`, `target_os`, `target_env`
+/// and `target_abi` cfgs.
+#[derive(Clone, Debug, PartialEq)]
+struct RustTarget {
+    arch: String,
+    os: String,
+    env: String,
+    abi: String,
+    // powerpc64 comes in both flavours, vcpkg only supports little endian
+    little_endian: bool,
+    // only used to pick between the arm-android and arm-neon-android triplets
+    neon: bool,
+}
+
+impl RustTarget {
+    /// Use the `CARGO_CFG_TARGET_*` variables set by cargo for build scripts,
+    /// falling back to picking apart `TARGET` if they are not available.
+    fn from_env() -> RustTarget {
+        match (
+            env::var("CARGO_CFG_TARGET_ARCH"),
+            env::var("CARGO_CFG_TARGET_OS"),
+        ) {
+            (Ok(arch), Ok(os)) => RustTarget {
+                arch: arch,
+                os: os,
+                env: env::var("CARGO_CFG_TARGET_ENV").unwrap_or(String::new()),
+                abi: env::var("CARGO_CFG_TARGET_ABI").unwrap_or(String::new()),
+                little_endian: env::var("CARGO_CFG_TARGET_ENDIAN")
+                    .map(|e| e == "little")
+                    .unwrap_or(true),
+                neon: env::var("CARGO_CFG_TARGET_FEATURE")
+                    .unwrap_or(String::new())
+                    .split(',')
+                    .any(|f| f == "neon"),
+            },
+            _ => RustTarget::from_target_str(&env::var("TARGET").unwrap_or(String::new())),
+        }
+    }
+
+    /// Split a target name like `armv7-linux-androideabi` or `x86_64-unknown-linux-gnu`
+    /// into its components.
+    fn from_target_str(target: &str) -> RustTarget {
+        let parts = target.split('-').collect::<Vec<_>>();
+
+        let cpu = parts[0];
+        let arch = if cpu == "i386" || cpu == "i586" || cpu == "i686" {
+            "x86"
+        } else if cpu == "arm64ec" {
+            "arm64ec"
+        } else if cpu.starts_with("aarch64") || cpu.starts_with("arm64") {
+            "aarch64"
+        } else if cpu.starts_with("arm") || cpu.starts_with("thumb") {
+            "arm"
+        } else if cpu.starts_with("powerpc64") {
+            "powerpc64"
+        } else if cpu.starts_with("riscv64") {
+            "riscv64"
+        } else if cpu.starts_with("riscv32") {
+            "riscv32"
+        } else if cpu.starts_with("mips64") {
+            "mips64"
+        } else {
+            cpu
+        };
+
+        // the vendor is optional, so find the operating system by name
+        let os_pos = parts
+            .iter()
+            .skip(1)
+            .position(|p| KNOWN_OSES.contains(p))
+            .map(|pos| pos + 1)
+            .unwrap_or(2);
+        let os = match (parts.get(os_pos), parts.get(os_pos + 1)) {
+            (Some(&"darwin"), _) => "macos",
+            // e.g. aarch64-linux-android
+            (_, Some(tail)) if tail.starts_with("android") => "android",
+            (Some(os), _) => os,
+            (None, _) => "",
+        };
+
+        let (env, mut abi) = match parts.get(os_pos + 1) {
+            Some(&"msvc") => ("msvc", ""),
+            Some(tail) if tail.starts_with("gnu") => ("gnu", &tail[3..]),
+            Some(tail) if tail.starts_with("musl") => ("musl", &tail[4..]),
+            Some(tail) if tail.starts_with("android") => ("", &tail[7..]),
+            Some(tail) => ("", *tail),
+            None => ("", ""),
+        };
+        if parts.get(1) == Some(&"uwp") {
+            abi = "uwp";
+        }
+
+        RustTarget {
+            arch: arch.to_owned(),
+            os: os.to_owned(),
+            env: env.to_owned(),
+            abi: abi.to_owned(),
+            little_endian: !cpu.starts_with("powerpc64") || cpu.ends_with("le"),
+            neon: cpu.contains("neon"),
+        }
+    }
+}
+
+// operating system names that can appear in a Rust target name
+const KNOWN_OSES: &'static [&'static str] = &[
+    "windows",
+    "linux",
+    "android",
+    "darwin",
+    "macos",
+    "ios",
+    "freebsd",
+    "openbsd",
+    "netbsd",
+    "emscripten",
+    "wasi",
+];
+
+// Rust target_arch to vcpkg VCPKG_TARGET_ARCHITECTURE, as used in triplet names
+const ARCH_TRIPLETS: &'static [(&'static str, &'static str)] = &[
+    ("x86_64", "x64"),
+    ("x86", "x86"),
+    ("aarch64", "arm64"),
+    ("arm", "arm"),
+    ("arm64ec", "arm64ec"),
+    ("wasm32", "wasm32"),
+    ("riscv64", "riscv64"),
+    ("riscv32", "riscv32"),
+    ("powerpc64", "ppc64le"),
+    ("s390x", "s390x"),
+    ("loongarch64", "loongarch64"),
+    ("mips64", "mips64"),
+];
+
+// Rust target_os to the system part of the name of the vcpkg triplets that
+// build static libraries for it. Windows is handled separately.
+const OS_TRIPLETS: &'static [(&'static str, &'static str)] = &[
+    ("linux", "linux"),
+    ("android", "android"),
+    ("macos", "osx"),
+    ("ios", "ios"),
+    ("freebsd", "freebsd"),
+    ("openbsd", "openbsd"),
+];
+
+fn lookup(table: &[(&str, &'static str)], key: &str) -> Option<&'static str> {
+    table.iter().find(|&&(k, _)| k == key).map(|&(_, v)| v)
+}
+
 fn detect_target_triplet() -> Result<TargetTriplet, Error> {
     let is_definitely_dynamic = env::var("VCPKGRS_DYNAMIC").is_ok();
-    let target = env::var("TARGET").unwrap_or(String::new());
     let is_static = env::var("CARGO_CFG_TARGET_FEATURE")
         .unwrap_or(String::new()) // rustc 1.10
         .contains("crt-static");
-    if target == "x86_64-apple-darwin" {
-        Ok(TargetTriplet {
-            triplet: "x64-osx".into(),
-            is_static: true,
-            lib_suffix: "a".into(),
-            strip_lib_prefix: true,
-        })
-    } else if target == "aarch64-apple-darwin" {
-        Ok(TargetTriplet {
-            triplet: "arm64-osx".into(),
-            is_static: true,
-            lib_suffix: "a".into(),
-            strip_lib_prefix: true,
-        })
-    } else if target == "x86_64-unknown-linux-gnu" {
-        Ok(TargetTriplet {
-            triplet: "x64-linux".into(),
-            is_static: true,
-            lib_suffix: "a".into(),
-            strip_lib_prefix: true,
-        })
-    } else if target == "aarch64-apple-ios" {
-        Ok(TargetTriplet {
-            triplet: "arm64-ios".into(),
-            is_static: true,
-            lib_suffix: "a".into(),
-            strip_lib_prefix: true,
-        })
-    } else if target.starts_with("wasm32-") {
-        Ok(TargetTriplet {
+    triplet_for_rust_target(&RustTarget::from_env(), is_static, is_definitely_dynamic)
+}
+
+// The triplet that vcpkg builds tools for, which is the same as vcpkg's own
+// choice of default host triplet.
+fn detect_host_triplet() -> Result<TargetTriplet, Error> {
+    if let Ok(triplet) = env::var("VCPKG_DEFAULT_HOST_TRIPLET") {
+        return Ok(triplet.into());
+    }
+    let host = match env::var("HOST") {
+        Ok(host) => RustTarget::from_target_str(&host),
+        Err(_) => return Err(Error::RequiredEnvMissing("HOST".to_owned())),
+    };
+    if host.os == "windows" {
+        // the tools are executables, so the linkage of the C runtime does not matter
+        match lookup(ARCH_TRIPLETS, &host.arch) {
+            Some(arch) => Ok(format!("{}-windows", arch).into()),
+            None => Err(Error::NotMSVC),
+        }
+    } else {
+        triplet_for_rust_target(&host, false, false)
+    }
+}
+
+fn triplet_for_rust_target(
+    target: &RustTarget,
+    is_static: bool,
+    is_definitely_dynamic: bool,
+) -> Result<TargetTriplet, Error> {
+    // vcpkg has a single triplet for all of the wasm32 targets
+    if target.arch == "wasm32" {
+        return Ok(TargetTriplet {
             triplet: "wasm32-emscripten".into(),
             is_static: true,
             lib_suffix: "a".into(),
             strip_lib_prefix: true,
-        })
-    } else if !target.contains("-pc-windows-msvc") && !target.contains("-win7-windows-msvc") {
-        Err(Error::NotMSVC)
-    } else if target.starts_with("x86_64-") {
-        if is_static {
-            Ok(TargetTriplet {
-                triplet: "x64-windows-static".into(),
-                is_static: true,
-                lib_suffix: "lib".into(),
-                strip_lib_prefix: false,
-            })
-        } else if is_definitely_dynamic {
-            Ok(TargetTriplet {
-                triplet: "x64-windows".into(),
-                is_static: false,
-                lib_suffix: "lib".into(),
-                strip_lib_prefix: false,
-            })
+            shared_lib_suffix: "so".into(),
+        });
+    }
+
+    let arch = match lookup(ARCH_TRIPLETS, &target.arch) {
+        Some(arch) if target.arch != "powerpc64" || target.little_endian => arch,
+        _ => return Err(Error::NotMSVC),
+    };
+
+    if target.os == "windows" && target.env == "gnu" {
+        // the MinGW triplets link the C runtime the same way Rust does, so the
+        // only choice is whether the ports are static or dynamic
+        return Ok(if is_definitely_dynamic {
+            format!("{}-mingw-dynamic", arch).into()
         } else {
-            Ok(TargetTriplet {
-                triplet: "x64-windows-static-md".into(),
-                is_static: true,
-                lib_suffix: "lib".into(),
-                strip_lib_prefix: false,
-            })
+            format!("{}-mingw-static", arch).into()
+        });
+    }
+
+    if target.os == "windows" {
+        if target.env != "msvc" {
+            return Err(Error::NotMSVC);
         }
-    } else if target.starts_with("aarch64") {
-        if is_static {
-            Ok(TargetTriplet {
-                triplet: "arm64-windows-static".into(),
-                is_static: true,
-                lib_suffix: "lib".into(),
-                strip_lib_prefix: false,
-            })
-        } else if is_definitely_dynamic {
-            Ok(TargetTriplet {
-                triplet: "arm64-windows".into(),
-                is_static: false,
-                lib_suffix: "lib".into(),
-                strip_lib_prefix: false,
-            })
+        let system = if target.abi == "uwp" {
+            "uwp"
         } else {
-            Ok(TargetTriplet {
-                triplet: "arm64-windows-static-md".into(),
-                is_static: true,
-                lib_suffix: "lib".into(),
-                strip_lib_prefix: false,
-            })
+            "windows"
+        };
+        // vcpkg has no uwp triplets that link the C runtime statically
+        if system == "uwp" && is_static {
+            return Err(Error::NotMSVC);
         }
-    } else {
-        // everything else is x86
-        if is_static {
-            Ok(TargetTriplet {
-                triplet: "x86-windows-static".into(),
-                is_static: true,
-                lib_suffix: "lib".into(),
-                strip_lib_prefix: false,
-            })
+        let (linkage, is_static) = if is_static {
+            ("-static", true)
         } else if is_definitely_dynamic {
-            Ok(TargetTriplet {
-                triplet: "x86-windows".into(),
-                is_static: false,
-                lib_suffix: "lib".into(),
-                strip_lib_prefix: false,
-            })
+            ("", false)
         } else {
-            Ok(TargetTriplet {
-                triplet: "x86-windows-static-md".into(),
-                is_static: true,
-                lib_suffix: "lib".into(),
-                strip_lib_prefix: false,
-            })
+            ("-static-md", true)
+        };
+        return Ok(TargetTriplet {
+            triplet: format!("{}-{}{}", arch, system, linkage),
+            is_static: is_static,
+            lib_suffix: "lib".into(),
+            strip_lib_prefix: false,
+            shared_lib_suffix: "dll".into(),
+        });
+    }
+
+    // the vcpkg triplets for Linux are built against glibc
+    if target.env == "musl" {
+        return Err(Error::NotMSVC);
+    }
+    let system = match lookup(OS_TRIPLETS, &target.os) {
+        Some(system) => system,
+        None => return Err(Error::NotMSVC),
+    };
+    // x64-ios is already a simulator triplet, there is no x64-ios-simulator
+    let triplet = if target.os == "ios" && target.abi == "sim" && arch != "x64" {
+        format!("{}-ios-simulator", arch)
+    } else if target.os == "android" && arch == "arm" && target.neon {
+        "arm-neon-android".to_owned()
+    } else {
+        format!("{}-{}", arch, system)
+    };
+    Ok(TargetTriplet {
+        triplet: triplet,
+        is_static: true,
+        lib_suffix: "a".into(),
+        strip_lib_prefix: true,
+        shared_lib_suffix: if system == "osx" || system == "ios" {
+            "dylib"
+        } else {
+            "so"
+        }
+        .into(),
+    })
+}
+
+// Find the name of the DLL that an import library refers to. Import libraries
+// are archives of COFF objects. MSVC writes short import members that name the
+// DLL directly, while MinGW import libraries contain an object with the name
+// of the DLL in its .idata$7 section. Returns None for static libraries, or if
+// the file can't be read.
+fn import_library_dll(path: &Path) -> Option<String> {
+    // import members are small, so bigger members are code and are skipped
+    // without being read, as are the linker members
+    const MAX_IMPORT_MEMBER: u64 = 64 * 1024;
+
+    let mut f = match File::open(path) {
+        Ok(f) => BufReader::new(f),
+        Err(_) => return None,
+    };
+    let mut magic = [0; 8];
+    if f.read_exact(&mut magic).is_err() || &magic != b"!<arch>\n" {
+        return None;
+    }
+
+    let mut header = [0; 60];
+    while f.read_exact(&mut header).is_ok() {
+        let size = match String::from_utf8_lossy(&header[48..58])
+            .trim()
+            .parse::<u64>()
+        {
+            Ok(size) => size,
+            Err(_) => return None,
+        };
+        // members are aligned to even offsets
+        let mut skip = size + (size & 1);
+
+        // skip the linker members and the long names member
+        if !header.starts_with(b"/ ") && !header.starts_with(b"// ") && size <= MAX_IMPORT_MEMBER {
+            let mut member = Vec::new();
+            match f.by_ref().take(size).read_to_end(&mut member) {
+                Ok(n) if n as u64 == size => {}
+                _ => return None,
+            }
+            skip -= size;
+
+            if member.len() >= 20 && member[0..6] == [0, 0, 0xff, 0xff, 0, 0] {
+                // short import format: a 20 byte header, then the symbol name
+                // and the DLL name, both nul terminated
+                let mut names = member[20..].split(|&b| b == 0);
+                names.next();
+                if let Some(dll) = names.next() {
+                    if !dll.is_empty() {
+                        return Some(String::from_utf8_lossy(dll).into_owned());
+                    }
+                }
+            } else if let Some(dll) = coff_import_dll(&member) {
+                return Some(dll);
+            }
+        }
+
+        if f.seek(SeekFrom::Current(skip as i64)).is_err() {
+            return None;
         }
     }
+    None
 }
 
-#[cfg(test)]
-mod tests {
+// The SHA-256 checksum of a file, in hex. The file is read in chunks, since
+// DLLs can be large.
+fn sha256_file(path: &Path) -> Option<String> {
+    let mut f = match File::open(path) {
+        Ok(f) => f,
+        Err(_) => return None,
+    };
+    let mut hasher = Sha256::new();
+    let mut buf = [0; 8192];
+    loop {
+        match f.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => hasher.update(&buf[..n]),
+            Err(_) => return None,
+        }
+    }
+    Some(
+        hasher
+            .finish()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect(),
+    )
+}
 
-    extern crate tempfile;
+fn sha256(data: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(data);
+    hasher.finish()
+}
 
-    use self::tempfile::tempdir;
-    use super::*;
-    use std::env;
-    use std::sync::Mutex;
+// SHA-256 as described in FIPS 180-4, fed a piece at a time.
+struct Sha256 {
+    h: [u32; 8],
+    // data that does not fill a block yet
+    pending: Vec<u8>,
+    len: u64,
+}
 
-    lazy_static! {
-        static ref LOCK: Mutex<()> = Mutex::new(());
+impl Sha256 {
+    fn new() -> Sha256 {
+        Sha256 {
+            h: [
+                0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+                0x5be0cd19,
+            ],
+            pending: Vec::with_capacity(64),
+            len: 0,
+        }
     }
 
-    #[test]
-    fn do_nothing_for_unsupported_target() {
-        let _g = LOCK.lock();
-        env::set_var("VCPKG_ROOT", "/");
-        env::set_var("TARGET", "x86_64-pc-windows-gnu");
-        assert!(match ::probe_package("foo") {
-            Err(Error::NotMSVC) => true,
-            _ => false,
-        });
+    fn update(&mut self, mut data: &[u8]) {
+        self.len = self.len.wrapping_add(data.len() as u64);
+        if !self.pending.is_empty() {
+            let n = std::cmp::min(64 - self.pending.len(), data.len());
+            self.pending.extend(&data[..n]);
+            data = &data[n..];
+            if self.pending.len() < 64 {
+                return;
+            }
+            let block = std::mem::replace(&mut self.pending, Vec::with_capacity(64));
+            self.compress(&block);
+        }
+        while data.len() >= 64 {
+            self.compress(&data[..64]);
+            data = &data[64..];
+        }
+        self.pending.extend(data);
+    }
 
-        env::set_var("TARGET", "x86_64-pc-windows-gnu");
-        assert_eq!(env::var("TARGET"), Ok("x86_64-pc-windows-gnu".to_string()));
-        assert!(match ::probe_package("foo") {
-            Err(Error::NotMSVC) => true,
-            _ => false,
-        });
-        env::remove_var("TARGET");
-        env::remove_var("VCPKG_ROOT");
+    fn finish(mut self) -> [u8; 32] {
+        let bit_len = self.len.wrapping_mul(8);
+        let mut padding = vec![0x80];
+        while (self.pending.len() + padding.len()) % 64 != 56 {
+            padding.push(0);
+        }
+        for i in (0..8).rev() {
+            padding.push((bit_len >> (i * 8)) as u8);
+        }
+        self.update(&padding);
+
+        let mut digest = [0u8; 32];
+        for i in 0..8 {
+            for j in 0..4 {
+                digest[i * 4 + j] = (self.h[i] >> (24 - j * 8)) as u8;
+            }
+        }
+        digest
     }
 
-    #[test]
-    fn do_nothing_for_bailout_variables_set() {
-        let _g = LOCK.lock();
-        env::set_var("VCPKG_ROOT", "/");
-        env::set_var("TARGET", "x86_64-pc-windows-msvc");
+    fn compress(&mut self, block: &[u8]) {
+        const K: [u32; 64] = [
+            0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4,
+            0xab1c5ed5, 0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe,
+            0x9bdc06a7, 0xc19bf174, 0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f,
+            0x4a7484aa, 0x5cb0a9dc, 0x76f988da, 0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7,
+            0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967, 0x27b70a85, 0x2e1b2138, 0x4d2c6dfc,
+            0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85, 0xa2bfe8a1, 0xa81a664b,
+            0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070, 0x19a4c116,
+            0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+            0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7,
+            0xc67178f2,
+        ];
+        let mut w = [0u32; 64];
+        for i in 0..16 {
+            w[i] = (block[i * 4] as u32) << 24
+                | (block[i * 4 + 1] as u32) << 16
+                | (block[i * 4 + 2] as u32) << 8
+                | block[i * 4 + 3] as u32;
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
 
-        for &var in &[
-            "VCPKGRS_DISABLE",
-            "VCPKGRS_NO_FOO",
-            "FOO_NO_VCPKG",
-            "NO_VCPKG",
-        ] {
-            env::set_var(var, "1");
-            assert!(match ::probe_package("foo") {
-                Err(Error::DisabledByEnv(ref v)) if v == var => true,
-                _ => false,
-            });
-            env::remove_var(var);
+        let mut v = self.h;
+        for i in 0..64 {
+            let s1 = v[4].rotate_right(6) ^ v[4].rotate_right(11) ^ v[4].rotate_right(25);
+            let ch = (v[4] & v[5]) ^ (!v[4] & v[6]);
+            let t1 = v[7]
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(K[i])
+                .wrapping_add(w[i]);
+            let s0 = v[0].rotate_right(2) ^ v[0].rotate_right(13) ^ v[0].rotate_right(22);
+            let maj = (v[0] & v[1]) ^ (v[0] & v[2]) ^ (v[1] & v[2]);
+            let t2 = s0.wrapping_add(maj);
+            v = [
+                t1.wrapping_add(t2),
+                v[0],
+                v[1],
+                v[2],
+                v[3].wrapping_add(t1),
+                v[4],
+                v[5],
+                v[6],
+            ];
+        }
+        for i in 0..8 {
+            self.h[i] = self.h[i].wrapping_add(v[i]);
         }
-        env::remove_var("TARGET");
-        env::remove_var("VCPKG_ROOT");
     }
+}
 
-    // these tests are good but are leaning on a real vcpkg installation
+// Put a copy of, or a link to, `src` at `dest` unless it is already there.
+// Returns whether `dest` was changed.
+fn deploy_file(src: &Path, dest: &Path, mode: DllCopyMode) -> std::io::Result<bool> {
+    let dest_is_link = fs::symlink_metadata(dest)
+        .map(|m| m.file_type().is_symlink())
+        .unwrap_or(false);
+    // a copy is also up to date in symlink mode, since copying is the fallback
+    // when links can't be created
+    let up_to_date = if dest_is_link {
+        mode == DllCopyMode::Symlink && fs::read_link(dest).map(|t| t == src).unwrap_or(false)
+    } else {
+        files_are_identical(src, dest)
+    };
+    if up_to_date {
+        return Ok(false);
+    }
 
-    // #[test]
-    // fn default_build_refuses_dynamic() {
-    //     let _g = LOCK.lock();
-    //     clean_env();
+    // remove the old file first, so that a link is replaced rather than the file
+    // in the vcpkg tree that it points to being overwritten
+    if dest_is_link || dest.exists() {
+        try!(fs::remove_file(dest));
+    }
+    let linked = match mode {
+        DllCopyMode::Copy => false,
+        DllCopyMode::HardLink => fs::hard_link(src, dest).is_ok(),
+        DllCopyMode::Symlink => symlink_file(src, dest).is_ok(),
+    };
+    if !linked {
+        try!(fs::copy(src, dest));
+    }
+    Ok(true)
+}
+
+fn files_are_identical(a: &Path, b: &Path) -> bool {
+    match (fs::metadata(a), fs::metadata(b)) {
+        (Ok(ref a), Ok(ref b)) if a.len() == b.len() => {}
+        _ => return false,
+    }
+    let (mut a, mut b) = match (File::open(a), File::open(b)) {
+        (Ok(a), Ok(b)) => (BufReader::new(a), BufReader::new(b)),
+        _ => return false,
+    };
+    let mut a_buf = [0; 8192];
+    let mut b_buf = [0; 8192];
+    loop {
+        let n = match a.read(&mut a_buf) {
+            Ok(0) => return true,
+            Ok(n) => n,
+            Err(_) => return false,
+        };
+        if b.read_exact(&mut b_buf[..n]).is_err() || a_buf[..n] != b_buf[..n] {
+            return false;
+        }
+    }
+}
+
+#[cfg(windows)]
+fn symlink_file(src: &Path, dest: &Path) -> std::io::Result<()> {
+    std::os::windows::fs::symlink_file(src, dest)
+}
+
+#[cfg(unix)]
+fn symlink_file(src: &Path, dest: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(src, dest)
+}
+
+#[cfg(not(any(windows, unix)))]
+fn symlink_file(_src: &Path, _dest: &Path) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Other,
+        "symbolic links are not supported",
+    ))
+}
+
+// The directories cargo puts binaries in, worked out from the OUT_DIR of a build
+// script, which is target/[<triple>/]<profile>/build/<package>-<hash>/out.
+// Returns nothing if OUT_DIR is not laid out like that.
+fn binary_dirs_from_out_dir(out_dir: &Path) -> Vec<PathBuf> {
+    if out_dir.file_name() != Some(OsStr::new("out")) {
+        return Vec::new();
+    }
+    let build_dir = match out_dir.parent().and_then(|p| p.parent()) {
+        Some(dir) if dir.file_name() == Some(OsStr::new("build")) => dir,
+        _ => return Vec::new(),
+    };
+    match build_dir.parent() {
+        Some(profile_dir) => vec![profile_dir.to_path_buf(), profile_dir.join("deps")],
+        None => Vec::new(),
+    }
+}
+
+// Where a crate found a port, as recorded in the target directory.
+#[derive(Clone, Debug, PartialEq)]
+struct ResolvedPort {
+    port: String,
+    triplet: String,
+    installed_root: String,
+    version: String,
+    crate_name: String,
+    // identifies the build script run that found the port
+    out_dir: String,
+}
+
+impl ResolvedPort {
+    fn parse(line: &str) -> Option<ResolvedPort> {
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() != 6 {
+            return None;
+        }
+        Some(ResolvedPort {
+            port: fields[0].to_owned(),
+            triplet: fields[1].to_owned(),
+            installed_root: fields[2].to_owned(),
+            version: fields[3].to_owned(),
+            crate_name: fields[4].to_owned(),
+            out_dir: fields[5].to_owned(),
+        })
+    }
+
+    fn to_line(&self) -> String {
+        format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\n",
+            self.port,
+            self.triplet,
+            self.installed_root,
+            self.version,
+            self.crate_name,
+            self.out_dir
+        )
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "{} (triplet {}, {}, version {})",
+            self.crate_name, self.triplet, self.installed_root, self.version
+        )
+    }
+}
+
+// A lock on the record of resolved ports, as build scripts run in parallel. The
+// lock file is removed when this is dropped.
+struct RecordLock(Option<PathBuf>);
+
+impl RecordLock {
+    fn acquire(path: &Path) -> RecordLock {
+        for _ in 0..500 {
+            match OpenOptions::new().write(true).create_new(true).open(path) {
+                Ok(_) => return RecordLock(Some(path.to_path_buf())),
+                Err(_) => {
+                    // a build script that was killed leaves its lock behind. The
+                    // lock is only held for as long as it takes to rewrite a small
+                    // file, so one that is older than a couple of seconds, or whose
+                    // age can't be worked out, has been left behind.
+                    let stale = fs::metadata(path)
+                        .and_then(|m| m.modified())
+                        .ok()
+                        .and_then(|t| SystemTime::now().duration_since(t).ok())
+                        .map(|age| age > Duration::from_secs(2))
+                        .unwrap_or(true);
+                    if stale {
+                        fs::remove_file(path).ok();
+                    } else {
+                        thread::sleep(Duration::from_millis(10));
+                    }
+                }
+            }
+        }
+        // checking without the lock is better than failing the build
+        RecordLock(None)
+    }
+}
+
+impl Drop for RecordLock {
+    fn drop(&mut self) {
+        if let Some(ref path) = self.0 {
+            fs::remove_file(path).ok();
+        }
+    }
+}
+
+// Record the ports that were found in a file shared by every crate in the build,
+// which is in the target directory, and report the ports that other crates found
+// in a different triplet or installed directory. The version is only recorded to
+// describe where a port was found, since a port that was upgraded in place is not
+// a conflict. Nothing is recorded unless OUT_DIR is in a cargo target directory.
+fn record_resolved_ports(lib: &mut Library) -> Result<(), Error> {
+    let mode = env::var("VCPKGRS_CONSISTENCY").unwrap_or(String::new());
+    if mode == "off" || lib.port_info.is_empty() {
+        return Ok(());
+    }
+    let out_dir = match env::var_os("OUT_DIR") {
+        Some(out_dir) => PathBuf::from(out_dir),
+        None => return Ok(()),
+    };
+    let profile_dir = match binary_dirs_from_out_dir(&out_dir).into_iter().next() {
+        Some(dir) => dir,
+        None => return Ok(()),
+    };
+    let crate_name = env::var("CARGO_PKG_NAME").unwrap_or("unknown".to_owned());
+    let out_dir = out_dir.display().to_string();
+
+    let found: Vec<ResolvedPort> = lib
+        .port_info
+        .iter()
+        .map(|(port, info)| ResolvedPort {
+            port: port.clone(),
+            triplet: lib.vcpkg_triplet.clone(),
+            installed_root: info.installed_root.display().to_string(),
+            version: info.version.clone(),
+            crate_name: crate_name.clone(),
+            out_dir: out_dir.clone(),
+        })
+        .collect();
+
+    let record = profile_dir.join("vcpkg-rs-resolved-ports.txt");
+    let _lock = RecordLock::acquire(&profile_dir.join("vcpkg-rs-resolved-ports.lock"));
+
+    let mut recorded = Vec::new();
+    let mut contents = String::new();
+    if File::open(&record)
+        .and_then(|mut f| f.read_to_string(&mut contents))
+        .is_ok()
+    {
+        let entries: Vec<ResolvedPort> = contents.lines().filter_map(ResolvedPort::parse).collect();
+        // entries are appended, so the last OUT_DIR of each crate is its newest
+        // build, and the others were left by builds with other features or
+        // dependencies, which are not part of this build
+        let mut newest_out_dirs = BTreeMap::new();
+        for entry in &entries {
+            newest_out_dirs.insert(entry.crate_name.clone(), entry.out_dir.clone());
+        }
+        newest_out_dirs.insert(crate_name.clone(), out_dir.clone());
+        for entry in entries {
+            if newest_out_dirs[&entry.crate_name] != entry.out_dir {
+                continue;
+            }
+            // replaced by what this build script found
+            if entry.out_dir == out_dir && found.iter().any(|f| f.port == entry.port) {
+                continue;
+            }
+            // the crate has been cleaned or removed since
+            if !Path::new(&entry.out_dir).exists() {
+                continue;
+            }
+            recorded.push(entry);
+        }
+    }
+
+    let mut conflicts = Vec::new();
+    for this in &found {
+        for other in &recorded {
+            if other.port == this.port
+                && other.crate_name != this.crate_name
+                && (other.triplet != this.triplet || other.installed_root != this.installed_root)
+            {
+                conflicts.push(format!(
+                    "package {} was found by {} and by {}",
+                    this.port,
+                    this.describe(),
+                    other.describe()
+                ));
+            }
+        }
+    }
+
+    let mut contents = String::new();
+    for entry in recorded.iter().chain(&found) {
+        contents.push_str(&entry.to_line());
+    }
+    if let Err(e) = File::create(&record).and_then(|mut f| f.write_all(contents.as_bytes())) {
+        lib.cargo_metadata.push(format!(
+            "cargo:warning=Can't record the ports that were found in {}: {}",
+            record.display(),
+            e
+        ));
+    }
+
+    if conflicts.is_empty() {
+        Ok(())
+    } else if mode == "error" {
+        Err(Error::Inconsistent(conflicts.join("; ")))
+    } else {
+        for conflict in conflicts {
+            lib.cargo_metadata
+                .push(format!("cargo:warning={}", conflict));
+        }
+        Ok(())
+    }
+}
+
+// Add the DLLs that the found DLLs need at runtime, following their import
+// tables. vcpkg can install DLLs into bin/ for dependencies that are not
+// declared, so the .list files of the ports are not enough.
+fn add_imported_dlls(lib: &mut Library, vcpkg_target: &VcpkgTarget) {
+    let mut bin_files = BTreeMap::new();
+    for root in vcpkg_target.roots() {
+        if let Ok(dir) = fs::read_dir(&root.bin_path) {
+            for entry in dir.filter_map(|e| e.ok()) {
+                if let Some(name) = entry.file_name().to_str() {
+                    bin_files.entry(name.to_lowercase()).or_insert(entry.path());
+                }
+            }
+        }
+    }
+
+    let mut seen: Vec<String> = lib
+        .found_dlls
+        .iter()
+        .filter_map(|dll| dll.file_name().and_then(|n| n.to_str()))
+        .map(|n| n.to_lowercase())
+        .collect();
+    let mut to_scan = lib.found_dlls.clone();
+    while let Some(dll) = to_scan.pop() {
+        // the test trees and some unusual ports have files that are not PE images
+        let imports = match pe_imported_dlls(&dll) {
+            Some(imports) => imports,
+            None => continue,
+        };
+        for import in imports {
+            let name = import.to_lowercase();
+            if seen.contains(&name) {
+                continue;
+            }
+            seen.push(name.clone());
+
+            if let Some(path) = bin_files.get(&name) {
+                lib.found_dlls.push(path.clone());
+                to_scan.push(path.clone());
+            } else if !is_system_dll(&name) {
+                lib.cargo_metadata.push(format!(
+                    "cargo:warning={} needs {}, which is not in {} or part of Windows",
+                    dll.display(),
+                    import,
+                    vcpkg_target.bin_path.display()
+                ));
+                lib.unresolved_dlls.push(import);
+            }
+        }
+    }
+}
+
+// DLLs that come with Windows, with the Visual C++ runtime that every MSVC program
+// needs anyway, or with the MinGW toolchain. When building on Windows, anything in
+// System32 counts as well. The name must be lower case.
+fn is_system_dll(name: &str) -> bool {
+    // API sets, which Windows resolves to its own DLLs
+    const SYSTEM_DLL_PREFIXES: &'static [&'static str] = &["api-ms-win-", "ext-ms-"];
+    const SYSTEM_DLLS: &'static [&'static str] = &[
+        "advapi32.dll",
+        "authz.dll",
+        "avrt.dll",
+        "bcrypt.dll",
+        "bluetoothapis.dll",
+        "cabinet.dll",
+        "cfgmgr32.dll",
+        "comctl32.dll",
+        "comdlg32.dll",
+        "comppkgsup.dll",
+        "concrt140.dll",
+        "concrt140d.dll",
+        "credui.dll",
+        "crypt32.dll",
+        "cryptbase.dll",
+        "cryptui.dll",
+        "d2d1.dll",
+        "d3d10.dll",
+        "d3d10_1.dll",
+        "d3d11.dll",
+        "d3d12.dll",
+        "d3d9.dll",
+        "d3dcompiler_47.dll",
+        "dbgeng.dll",
+        "dbghelp.dll",
+        "dcomp.dll",
+        "dhcpcsvc.dll",
+        "dinput8.dll",
+        "dnsapi.dll",
+        "dsound.dll",
+        "dwmapi.dll",
+        "dwrite.dll",
+        "dxgi.dll",
+        "dxva2.dll",
+        "evr.dll",
+        "fwpuclnt.dll",
+        "gdi32.dll",
+        "gdiplus.dll",
+        "glu32.dll",
+        "hid.dll",
+        "imagehlp.dll",
+        "imm32.dll",
+        "iphlpapi.dll",
+        "kernel32.dll",
+        "kernelbase.dll",
+        "ksuser.dll",
+        "mf.dll",
+        "mfplat.dll",
+        "mfreadwrite.dll",
+        "mpr.dll",
+        "msacm32.dll",
+        "msi.dll",
+        "msimg32.dll",
+        "msvcp140.dll",
+        "msvcp140_1.dll",
+        "msvcp140_2.dll",
+        "msvcp140_atomic_wait.dll",
+        "msvcp140_codecvt_ids.dll",
+        "msvcp140d.dll",
+        "msvcp140d_1.dll",
+        "msvcp140d_2.dll",
+        "msvcrt.dll",
+        "mswsock.dll",
+        "ncrypt.dll",
+        "netapi32.dll",
+        "normaliz.dll",
+        "ntdll.dll",
+        "ntdsapi.dll",
+        "odbc32.dll",
+        "ole32.dll",
+        "oleaut32.dll",
+        "oledlg.dll",
+        "opengl32.dll",
+        "pdh.dll",
+        "powrprof.dll",
+        "propsys.dll",
+        "psapi.dll",
+        "rasapi32.dll",
+        "rpcrt4.dll",
+        "secur32.dll",
+        "setupapi.dll",
+        "shcore.dll",
+        "shell32.dll",
+        "shfolder.dll",
+        "shlwapi.dll",
+        "sspicli.dll",
+        "tdh.dll",
+        "ucrtbase.dll",
+        "ucrtbased.dll",
+        "urlmon.dll",
+        "user32.dll",
+        "userenv.dll",
+        "usp10.dll",
+        "uxtheme.dll",
+        "vccorlib140.dll",
+        "vccorlib140d.dll",
+        "vcruntime140.dll",
+        "vcruntime140_1.dll",
+        "vcruntime140_1d.dll",
+        "vcruntime140d.dll",
+        "version.dll",
+        "virtdisk.dll",
+        "wer.dll",
+        "wevtapi.dll",
+        "windowscodecs.dll",
+        "winhttp.dll",
+        "wininet.dll",
+        "winmm.dll",
+        "winscard.dll",
+        "winspool.drv",
+        "wintrust.dll",
+        "winusb.dll",
+        "wldap32.dll",
+        "ws2_32.dll",
+        "wsock32.dll",
+        "wtsapi32.dll",
+        "xinput1_4.dll",
+    ];
+    // the runtimes of the MinGW compilers, which come with the toolchain
+    const MINGW_DLLS: &'static [&'static str] = &[
+        "libatomic-1.dll",
+        "libgcc_s_dw2-1.dll",
+        "libgcc_s_seh-1.dll",
+        "libgcc_s_sjlj-1.dll",
+        "libgfortran-5.dll",
+        "libgomp-1.dll",
+        "libquadmath-0.dll",
+        "libssp-0.dll",
+        "libstdc++-6.dll",
+        "libwinpthread-1.dll",
+    ];
+    if SYSTEM_DLLS.contains(&name)
+        || MINGW_DLLS.contains(&name)
+        || SYSTEM_DLL_PREFIXES.iter().any(|p| name.starts_with(p))
+    {
+        return true;
+    }
+    match env::var_os("SystemRoot") {
+        Some(root) => Path::new(&root).join("System32").join(name).is_file(),
+        None => false,
+    }
+}
+
+// The names of the DLLs imported by a PE image, including delay loaded DLLs.
+// Returns None if the file is not a PE image.
+fn pe_imported_dlls(path: &Path) -> Option<Vec<String>> {
+    let mut data = Vec::new();
+    if File::open(path)
+        .and_then(|mut f| f.read_to_end(&mut data))
+        .is_err()
+    {
+        return None;
+    }
+    if data.len() < 0x40 || !data.starts_with(b"MZ") {
+        return None;
+    }
+    let pe = read_u32(&data, 0x3c) as usize;
+    if pe + 24 > data.len() || &data[pe..pe + 4] != b"PE\0\0" {
+        return None;
+    }
+    let number_of_sections = read_u16(&data, pe + 6) as usize;
+    let optional_header = pe + 24;
+    let section_table = optional_header + read_u16(&data, pe + 20) as usize;
+    if optional_header + 2 > data.len() {
+        return None;
+    }
+    let data_directories = match read_u16(&data, optional_header) {
+        0x10b => optional_header + 96,  // PE32
+        0x20b => optional_header + 112, // PE32+
+        _ => return None,
+    };
+
+    // map a relative virtual address to an offset in the file
+    let rva_to_offset = |rva: usize| -> Option<usize> {
+        for i in 0..number_of_sections {
+            let section = section_table + i * 40;
+            if section + 40 > data.len() {
+                return None;
+            }
+            let virtual_size = read_u32(&data, section + 8) as usize;
+            let virtual_address = read_u32(&data, section + 12) as usize;
+            let raw_size = read_u32(&data, section + 16) as usize;
+            let raw_offset = read_u32(&data, section + 20) as usize;
+            if rva >= virtual_address
+                && rva < virtual_address + std::cmp::max(virtual_size, raw_size)
+            {
+                return Some(rva - virtual_address + raw_offset);
+            }
+        }
+        None
+    };
+    let read_name = |rva: usize| -> Option<String> {
+        match rva_to_offset(rva) {
+            Some(offset) if offset <= data.len() => {
+                let name = data[offset..].split(|&b| b == 0).next().unwrap_or(&[]);
+                Some(String::from_utf8_lossy(name).into_owned())
+            }
+            _ => None,
+        }
+    };
+
+    let mut dlls = Vec::new();
+    // the import directory has 20 byte entries with the name at offset 12, and
+    // the delay import directory has 32 byte entries with the name at offset 4
+    for &(directory, entry_size, name_offset) in &[(1, 20, 12), (13, 32, 4)] {
+        let entry = data_directories + directory * 8;
+        if entry + 8 > data.len() || entry >= section_table {
+            continue;
+        }
+        let rva = read_u32(&data, entry) as usize;
+        if rva == 0 {
+            continue;
+        }
+        let mut offset = match rva_to_offset(rva) {
+            Some(offset) => offset,
+            None => continue,
+        };
+        while offset + entry_size <= data.len() {
+            let name_rva = read_u32(&data, offset + name_offset) as usize;
+            if name_rva == 0 {
+                break;
+            }
+            if let Some(name) = read_name(name_rva) {
+                dlls.push(name);
+            }
+            offset += entry_size;
+        }
+    }
+    Some(dlls)
+}
+
+// Look for the name of a DLL in the import data sections of a COFF object.
+fn coff_import_dll(object: &[u8]) -> Option<String> {
+    if object.len() < 20 {
+        return None;
+    }
+    let number_of_sections = read_u16(object, 2) as usize;
+    let section_table = 20 + read_u16(object, 16) as usize;
+    for i in 0..number_of_sections {
+        let section = section_table + i * 40;
+        if section + 40 > object.len() {
+            return None;
+        }
+        let name = &object[section..section + 8];
+        if name != b".idata$6" && name != b".idata$7" {
+            continue;
+        }
+        let size = read_u32(object, section + 16) as usize;
+        let offset = read_u32(object, section + 20) as usize;
+        if offset > object.len() || size > object.len() - offset {
+            continue;
+        }
+        for s in object[offset..offset + size].split(|&b| b == 0) {
+            let s = String::from_utf8_lossy(s);
+            if s.to_lowercase().ends_with(".dll") {
+                return Some(s.into_owned());
+            }
+        }
+    }
+    None
+}
+
+fn read_u16(data: &[u8], pos: usize) -> u16 {
+    data[pos] as u16 | (data[pos + 1] as u16) << 8
+}
+
+fn read_u32(data: &[u8], pos: usize) -> u32 {
+    read_u16(data, pos) as u32 | (read_u16(data, pos + 2) as u32) << 16
+}
+
+// Fill in the license and home page of a port from the SPDX document vcpkg
+// writes for it. This is extra information, so problems are ignored.
+fn read_spdx_file(path: &Path, port: &str, info: &mut PortInfo) {
+    let mut contents = String::new();
+    if File::open(path)
+        .and_then(|mut f| f.read_to_string(&mut contents))
+        .is_err()
+    {
+        return;
+    }
+    // the package of the port is the one with its name, and its fields run up
+    // to the name of the next package
+    let members = json_string_members(&contents);
+    let start = match members
+        .iter()
+        .position(|&(ref key, ref value)| key == "name" && value == port)
+    {
+        Some(start) => start + 1,
+        None => return,
+    };
+    let package: Vec<&(String, String)> = members[start..]
+        .iter()
+        .take_while(|&&(ref key, _)| key != "name")
+        .collect();
+
+    let known = |key: &str| {
+        package.iter().find(|&&&(ref k, _)| k == key).and_then(
+            |&&(_, ref value)| match &value[..] {
+                "" | "NOASSERTION" | "NONE" => None,
+                v => Some(v.to_owned()),
+            },
+        )
+    };
+    info.license = known("licenseConcluded").or(known("licenseDeclared"));
+    info.homepage = known("homepage");
+}
+
+// The members of the JSON objects in `text` that have string values, in the order
+// they appear, without regard to which object they are in. This is just enough to
+// pick fields out of the SPDX documents written by vcpkg.
+fn json_string_members(text: &str) -> Vec<(String, String)> {
+    let mut members = Vec::new();
+    let mut chars = text.chars();
+    let mut last_string = None;
+    let mut key = None;
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                let s = read_json_string(&mut chars);
+                match key.take() {
+                    Some(key) => members.push((key, s)),
+                    None => last_string = Some(s),
+                }
+            }
+            ':' => key = last_string.take(),
+            c if c.is_whitespace() => {}
+            _ => {
+                last_string = None;
+                key = None;
+            }
+        }
+    }
+    members
+}
+
+// The rest of a JSON string whose opening quote has been read.
+fn read_json_string(chars: &mut std::str::Chars) -> String {
+    let mut s = String::new();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => break,
+            '\\' => match chars.next() {
+                Some('n') => s.push('\n'),
+                Some('t') => s.push('\t'),
+                Some('r') => s.push('\r'),
+                Some('u') => {
+                    let hex: String = chars.by_ref().take(4).collect();
+                    // surrogate pairs are not worth the trouble here
+                    s.push(
+                        u32::from_str_radix(&hex, 16)
+                            .ok()
+                            .and_then(std::char::from_u32)
+                            .unwrap_or('\u{fffd}'),
+                    );
+                }
+                Some(c) => s.push(c),
+                None => break,
+            },
+            c => s.push(c),
+        }
+    }
+    s
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+
+    extern crate tempfile;
+
+    use self::tempfile::tempdir;
+    use super::*;
+    use std::env;
+    use std::sync::Mutex;
+
+    lazy_static! {
+        static ref LOCK: Mutex<()> = Mutex::new(());
+    }
+
+    #[test]
+    fn do_nothing_for_unsupported_target() {
+        let _g = LOCK.lock();
+        env::set_var("VCPKG_ROOT", "/");
+        env::set_var("TARGET", "sparc64-unknown-linux-gnu");
+        assert!(match ::probe_package("foo") {
+            Err(Error::NotMSVC) => true,
+            _ => false,
+        });
+
+        env::set_var("TARGET", "powerpc64-unknown-linux-gnu");
+        assert_eq!(
+            env::var("TARGET"),
+            Ok("powerpc64-unknown-linux-gnu".to_string())
+        );
+        assert!(match ::probe_package("foo") {
+            Err(Error::NotMSVC) => true,
+            _ => false,
+        });
+        env::remove_var("TARGET");
+        env::remove_var("VCPKG_ROOT");
+    }
+
+    #[test]
+    fn do_nothing_for_bailout_variables_set() {
+        let _g = LOCK.lock();
+        env::set_var("VCPKG_ROOT", "/");
+        env::set_var("TARGET", "x86_64-pc-windows-msvc");
+
+        for &var in &[
+            "VCPKGRS_DISABLE",
+            "VCPKGRS_NO_FOO",
+            "FOO_NO_VCPKG",
+            "NO_VCPKG",
+        ] {
+            env::set_var(var, "1");
+            assert!(match ::probe_package("foo") {
+                Err(Error::DisabledByEnv(ref v)) if v == var => true,
+                _ => false,
+            });
+            env::remove_var(var);
+        }
+        env::remove_var("TARGET");
+        env::remove_var("VCPKG_ROOT");
+    }
+
+    // these tests are good but are leaning on a real vcpkg installation
+
+    // #[test]
+    // fn default_build_refuses_dynamic() {
+    //     let _g = LOCK.lock();
+    //     clean_env();
     //     env::set_var("VCPKG_ROOT", vcpkg_test_tree_loc("no-status"));
     //     env::set_var("TARGET", "x86_64-pc-windows-msvc");
     //     println!("Result is {:?}", ::find_package("libmysql"));
@@ -1551,6 +4738,54 @@ mod tests {
         clean_env();
     }
 
+    #[test]
+    fn crt_mismatch_is_reported() {
+        let _g = LOCK.lock();
+        clean_env();
+        env::set_var("VCPKG_ROOT", vcpkg_test_tree_loc("normalized"));
+        env::set_var("TARGET", "x86_64-pc-windows-msvc");
+        let tmp_dir = tempdir().unwrap();
+        env::set_var("OUT_DIR", tmp_dir.path());
+
+        let strict_find = || {
+            ::Config::new()
+                .cargo_metadata(false)
+                .crt_mismatch_is_error(true)
+                .find_package("libmysql")
+        };
+        let crt_warning = |lib: &Library| {
+            lib.cargo_metadata
+                .iter()
+                .any(|l| l.starts_with("cargo:warning=") && l.contains("C runtime"))
+        };
+
+        env::set_var("VCPKGRS_TRIPLET", "x64-windows-static-md");
+        env::set_var("CARGO_CFG_TARGET_FEATURE", "crt-static,sse2");
+        assert!(match strict_find() {
+            Err(Error::CrtMismatch(ref detail)) => detail.contains("+crt-static"),
+            _ => false,
+        });
+
+        env::set_var("VCPKGRS_TRIPLET", "x64-windows-static");
+        env::set_var("CARGO_CFG_TARGET_FEATURE", "sse2");
+        assert!(match strict_find() {
+            Err(Error::CrtMismatch(ref detail)) => detail.contains("statically"),
+            _ => false,
+        });
+        // pure C libraries can link anyway, so it is only a warning by default
+        let lib = ::Config::new()
+            .cargo_metadata(false)
+            .find_package("libmysql")
+            .unwrap();
+        assert!(crt_warning(&lib));
+
+        // not running under cargo, so there is nothing to compare against
+        env::remove_var("CARGO_CFG_TARGET_FEATURE");
+        let lib = strict_find().unwrap();
+        assert!(!crt_warning(&lib));
+        clean_env();
+    }
+
     #[test]
     fn dynamic_build_finds_lib() {
         let _g = LOCK.lock();
@@ -1626,17 +4861,1186 @@ mod tests {
             let tmp_dir = tempdir().unwrap();
             env::set_var("OUT_DIR", tmp_dir.path());
 
-            println!("Result is {:?}", ::find_package("harfbuzz"));
-            assert!(match ::find_package("harfbuzz") {
-                Ok(lib) => lib
-                    .cargo_metadata
-                    .iter()
-                    .find(|&x| x == "cargo:rustc-link-lib=harfbuzz")
-                    .is_some(),
-                _ => false,
-            });
-            clean_env();
+            println!("Result is {:?}", ::find_package("harfbuzz"));
+            assert!(match ::find_package("harfbuzz") {
+                Ok(lib) => lib
+                    .cargo_metadata
+                    .iter()
+                    .find(|&x| x == "cargo:rustc-link-lib=harfbuzz")
+                    .is_some(),
+                _ => false,
+            });
+            clean_env();
+        }
+    }
+
+    #[test]
+    fn tools_of_ports_are_found() {
+        let _g = LOCK.lock();
+
+        for &(target, tool) in &[
+            ("x86_64-apple-darwin", "x64-osx/tools/ragel"),
+            ("i686-pc-windows-msvc", "x86-windows/tools/ragel.exe"),
+        ] {
+            clean_env();
+            env::set_var("VCPKG_ROOT", vcpkg_test_tree_loc("normalized"));
+            env::set_var("TARGET", target);
+            env::set_var("VCPKGRS_DYNAMIC", "1");
+            let tmp_dir = tempdir().unwrap();
+            env::set_var("OUT_DIR", tmp_dir.path());
+
+            // ragel is a dependency of harfbuzz
+            let lib = ::find_package("harfbuzz").unwrap();
+            let tools_dir = vcpkg_test_tree_loc("normalized")
+                .join("installed")
+                .join(&lib.vcpkg_triplet)
+                .join("tools");
+            assert!(lib.tools.values().all(|path| path.starts_with(&tools_dir)));
+            assert_eq!(
+                lib.tools["ragel"],
+                vcpkg_test_tree_loc("normalized")
+                    .join("installed")
+                    .join(tool)
+            );
+            clean_env();
+        }
+    }
+
+    #[test]
+    fn headers_and_share_files_of_ports() {
+        let _g = LOCK.lock();
+        clean_env();
+        env::set_var("VCPKG_ROOT", vcpkg_test_tree_loc("normalized"));
+        env::set_var("TARGET", "i686-pc-windows-msvc");
+        env::set_var("VCPKGRS_DYNAMIC", "1");
+        let tmp_dir = tempdir().unwrap();
+        env::set_var("OUT_DIR", tmp_dir.path());
+
+        let lib = ::find_package("harfbuzz").unwrap();
+        let installed = vcpkg_test_tree_loc("normalized")
+            .join("installed")
+            .join("x86-windows");
+
+        let headers = &lib.headers["harfbuzz"];
+        assert!(headers.contains(&installed.join("include/harfbuzz/hb-blob.h")));
+        assert!(headers
+            .iter()
+            .all(|h| h.starts_with(installed.join("include"))));
+        assert!(!lib.headers["ragel"]
+            .iter()
+            .any(|h| h.ends_with("hb-blob.h")));
+
+        assert!(lib.share_files["ragel"].contains(&installed.join("share/ragel/copyright")));
+        assert!(lib.share_files["harfbuzz"]
+            .iter()
+            .all(|f| f.starts_with(installed.join("share"))));
+        clean_env();
+    }
+
+    #[test]
+    fn licenses_are_collected() {
+        let _g = LOCK.lock();
+        clean_env();
+        let tmp_dir = tempdir().unwrap();
+        let root = tmp_dir.path().join("vcpkg");
+        write_test_tree(
+            &root,
+            "x64-linux",
+            &[
+                (
+                    "libpng",
+                    "1.6.37",
+                    "zlib",
+                    &[
+                        "lib/libpng16.a",
+                        "share/libpng/copyright",
+                        "share/libpng/usage",
+                    ],
+                ),
+                ("zlib", "1.2.11", "", &["lib/libz.a", "include/zlib.h"]),
+            ],
+        );
+        fs::write(
+            root.join("installed/x64-linux/share/libpng/copyright"),
+            "libpng license",
+        )
+        .unwrap();
+        env::set_var("VCPKG_ROOT", &root);
+        env::set_var("TARGET", "x86_64-unknown-linux-gnu");
+        let out_dir = tmp_dir.path().join("out");
+        fs::create_dir_all(&out_dir).unwrap();
+        env::set_var("OUT_DIR", &out_dir);
+
+        let lib = ::Config::new()
+            .cargo_metadata(false)
+            .copy_licenses(true)
+            .find_package("libpng")
+            .unwrap();
+        let copyright_files = lib.copyright_files();
+        assert_eq!(copyright_files.len(), 1);
+        assert_eq!(
+            copyright_files["libpng"],
+            root.join("installed/x64-linux/share/libpng/copyright")
+        );
+
+        let licenses = out_dir.join("licenses");
+        assert_eq!(
+            fs::read_to_string(licenses.join("libpng/copyright")).unwrap(),
+            "libpng license"
+        );
+        let manifest = fs::read_to_string(licenses.join("licenses.txt")).unwrap();
+        assert!(manifest.contains("libpng\tlibpng/copyright\n"));
+        assert!(manifest.contains("zlib\tnone\n"));
+        clean_env();
+    }
+
+    #[test]
+    fn sbom_of_linked_ports() {
+        let _g = LOCK.lock();
+        clean_env();
+        let tmp_dir = tempdir().unwrap();
+        let root = tmp_dir.path().join("vcpkg");
+        write_test_tree(
+            &root,
+            "x64-linux",
+            &[
+                ("libpng", "1.6.37", "zlib", &["lib/libpng16.a"]),
+                (
+                    "zlib",
+                    "1.2.11",
+                    "",
+                    &["lib/libz.a", "share/zlib/vcpkg.spdx.json"],
+                ),
+            ],
+        );
+        // a later install of libpng records more details
+        fs::write(
+            root.join("installed/vcpkg/updates/000001"),
+            "Package: libpng\nVersion: 1.6.37\nPort-Version: 2\nDepends: zlib, vcpkg-cmake\n\
+             Architecture: x64-linux\nMulti-Arch: same\nAbi: 4f2e9a\n\
+             Status: install ok installed\n",
+        )
+        .unwrap();
+        fs::write(
+            root.join("installed/x64-linux/share/zlib/vcpkg.spdx.json"),
+            r#"{
+  "spdxVersion": "SPDX-2.2",
+  "packages": [
+    {
+      "name": "zlib",
+      "SPDXID": "SPDXRef-port",
+      "versionInfo": "1.2.11",
+      "homepage": "https://www.zlib.net/",
+      "licenseConcluded": "Zlib",
+      "licenseDeclared": "NOASSERTION",
+      "description": "A \"massively spiffy\" compression library\u0021",
+      "files": [],
+      "filesAnalyzed": false
+    },
+    { "name": "madler/zlib", "licenseConcluded": "NOASSERTION" }
+  ]
+}"#,
+        )
+        .unwrap();
+        env::set_var("VCPKG_ROOT", &root);
+        env::set_var("TARGET", "x86_64-unknown-linux-gnu");
+        env::set_var("OUT_DIR", tmp_dir.path());
+
+        let lib = ::Config::new()
+            .cargo_metadata(false)
+            .find_package("libpng")
+            .unwrap();
+        assert_eq!(
+            lib.port_info["libpng"],
+            PortInfo {
+                version: "1.6.37#2".to_owned(),
+                abi: Some("4f2e9a".to_owned()),
+                deps: vec!["zlib".to_owned()],
+                license: None,
+                homepage: None,
+                installed_root: root.join("installed"),
+            }
+        );
+        assert_eq!(lib.port_info["zlib"].abi, None);
+        assert_eq!(lib.port_info["zlib"].license, Some("Zlib".to_owned()));
+        assert_eq!(
+            lib.port_info["zlib"].homepage,
+            Some("https://www.zlib.net/".to_owned())
+        );
+
+        let sbom = lib.sbom_json();
+        let sbom = parse_json(&sbom).expect("the SBOM is valid JSON");
+        assert_eq!(
+            sbom.get("bomFormat").and_then(|v| v.as_str()),
+            Some("CycloneDX")
+        );
+        let components = match sbom.get("components") {
+            Some(&JsonValue::Array(ref components)) => components.clone(),
+            _ => panic!("no components"),
+        };
+        assert_eq!(components.len(), 2);
+        let zlib = components
+            .iter()
+            .find(|c| c.get("name").and_then(|v| v.as_str()) == Some("zlib"))
+            .unwrap();
+        assert_eq!(
+            zlib.get("bom-ref").and_then(|v| v.as_str()),
+            Some("vcpkg:zlib:x64-linux")
+        );
+        assert_eq!(
+            sbom.get("dependencies"),
+            Some(&JsonValue::Array(vec![
+                JsonValue::Object(vec![
+                    (
+                        "ref".to_owned(),
+                        JsonValue::String("vcpkg:libpng:x64-linux".to_owned())
+                    ),
+                    (
+                        "dependsOn".to_owned(),
+                        JsonValue::Array(vec![JsonValue::String(
+                            "vcpkg:zlib:x64-linux".to_owned()
+                        )])
+                    ),
+                ]),
+                JsonValue::Object(vec![
+                    (
+                        "ref".to_owned(),
+                        JsonValue::String("vcpkg:zlib:x64-linux".to_owned())
+                    ),
+                    ("dependsOn".to_owned(), JsonValue::Array(vec![])),
+                ]),
+            ]))
+        );
+        clean_env();
+    }
+
+    #[test]
+    fn dll_names_from_import_libraries() {
+        let tmp_dir = tempdir().unwrap();
+
+        let msvc = tmp_dir.path().join("bz2.lib");
+        fs::write(
+            &msvc,
+            ar_archive(&[
+                ("/", vec![0; 4]),
+                ("bz2.dll", coff_object(".idata$6", b"libbz2.dll\0")),
+                ("bz2.dll", short_import("BZ2_bzRead", "libbz2.dll")),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(import_library_dll(&msvc), Some("libbz2.dll".to_owned()));
+
+        let mingw = tmp_dir.path().join("libzlib.dll.a");
+        fs::write(
+            &mingw,
+            ar_archive(&[
+                ("d000001.o", coff_object(".idata$6", b"\0\0inflate\0")),
+                ("d000002.o", coff_object(".idata$7", b"zlib1.dll\0")),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(import_library_dll(&mingw), Some("zlib1.dll".to_owned()));
+
+        let static_lib = tmp_dir.path().join("zlib.lib");
+        fs::write(
+            &static_lib,
+            ar_archive(&[("inflate.obj", coff_object(".text", b"\xc3"))]),
+        )
+        .unwrap();
+        assert_eq!(import_library_dll(&static_lib), None);
+
+        // the test trees contain empty files
+        let empty = tmp_dir.path().join("empty.lib");
+        File::create(&empty).unwrap();
+        assert_eq!(import_library_dll(&empty), None);
+
+        // member sizes that run past the end of the file
+        let corrupt = tmp_dir.path().join("corrupt.lib");
+        let mut data = ar_archive(&[("zlib1.dll", short_import("inflate", "zlib1.dll"))]);
+        data[8 + 48..8 + 58].copy_from_slice(b"9999999999");
+        fs::write(&corrupt, &data).unwrap();
+        assert_eq!(import_library_dll(&corrupt), None);
+        let mut object = coff_object(".idata$7", b"zlib1.dll\0");
+        for b in &mut object[20 + 16..20 + 24] {
+            *b = 0xff;
+        }
+        assert_eq!(coff_import_dll(&object), None);
+    }
+
+    #[test]
+    fn only_imported_dlls_are_found() {
+        let _g = LOCK.lock();
+        clean_env();
+        let tmp_dir = tempdir().unwrap();
+        let root = tmp_dir.path().join("vcpkg");
+        write_test_tree(
+            &root,
+            "x64-windows",
+            &[(
+                "zlib",
+                "1.2.11",
+                "",
+                &["lib/zlib.lib", "bin/zlib1.dll", "bin/minizip.dll"],
+            )],
+        );
+        let import_lib = root.join("installed/x64-windows/lib/zlib.lib");
+        fs::write(
+            &import_lib,
+            ar_archive(&[("zlib1.dll", short_import("inflate", "zlib1.dll"))]),
+        )
+        .unwrap();
+        env::set_var("VCPKG_ROOT", &root);
+        env::set_var("TARGET", "x86_64-pc-windows-msvc");
+        env::set_var("VCPKGRS_DYNAMIC", "1");
+        env::set_var("OUT_DIR", tmp_dir.path());
+
+        let lib = ::Config::new()
+            .cargo_metadata(false)
+            .find_package("zlib")
+            .unwrap();
+        let zlib1 = root.join("installed/x64-windows/bin/zlib1.dll");
+        assert_eq!(lib.lib_dlls[&import_lib], zlib1);
+        assert_eq!(lib.found_dlls, vec![zlib1.clone()]);
+        assert!(tmp_dir.path().join("zlib1.dll").exists());
+        assert!(!tmp_dir.path().join("minizip.dll").exists());
+
+        // a DLL that the import library names but that isn't installed is
+        // reported, rather than the import library
+        fs::write(
+            &import_lib,
+            ar_archive(&[("zlib2.dll", short_import("inflate", "zlib2.dll"))]),
+        )
+        .unwrap();
+        match ::Config::new().cargo_metadata(false).find_package("zlib") {
+            Err(Error::LibNotFound(ref detail)) => {
+                assert!(detail.contains("zlib2.dll is imported by"), "{}", detail);
+                assert!(detail.contains("zlib.lib"), "{}", detail);
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+        fs::write(
+            &import_lib,
+            ar_archive(&[("zlib1.dll", short_import("inflate", "zlib1.dll"))]),
+        )
+        .unwrap();
+
+        // DLLs named explicitly are required as well
+        let lib = ::Config::new()
+            .cargo_metadata(false)
+            .lib_names("zlib", "minizip")
+            .find_package("zlib")
+            .unwrap();
+        let minizip = root.join("installed/x64-windows/bin/minizip.dll");
+        assert_eq!(lib.found_dlls, vec![zlib1, minizip]);
+        clean_env();
+    }
+
+    #[test]
+    fn imported_dlls_are_followed() {
+        let _g = LOCK.lock();
+        clean_env();
+        let tmp_dir = tempdir().unwrap();
+        let root = tmp_dir.path().join("vcpkg");
+        write_test_tree(
+            &root,
+            "x64-windows",
+            &[
+                ("zlib", "1.2.11", "", &["lib/zlib.lib", "bin/zlib1.dll"]),
+                // a port that zlib does not declare as a dependency
+                ("openssl", "3.0.8", "", &["bin/libcrypto-3-x64.dll"]),
+            ],
+        );
+        let bin = root.join("installed/x64-windows/bin");
+        fs::write(
+            root.join("installed/x64-windows/lib/zlib.lib"),
+            ar_archive(&[("zlib1.dll", short_import("inflate", "zlib1.dll"))]),
+        )
+        .unwrap();
+        fs::write(
+            bin.join("zlib1.dll"),
+            pe_file(
+                &["KERNEL32.dll", "libcrypto-3-x64.dll", "VCRUNTIME140.dll"],
+                &["cudart64_12.dll"],
+            ),
+        )
+        .unwrap();
+        fs::write(
+            bin.join("libcrypto-3-x64.dll"),
+            pe_file(
+                &["WS2_32.dll", "ZLIB1.dll", "api-ms-win-crt-heap-l1-1-0.dll"],
+                &[],
+            ),
+        )
+        .unwrap();
+        env::set_var("VCPKG_ROOT", &root);
+        env::set_var("TARGET", "x86_64-pc-windows-msvc");
+        env::set_var("VCPKGRS_DYNAMIC", "1");
+        env::set_var("OUT_DIR", tmp_dir.path());
+
+        assert_eq!(
+            pe_imported_dlls(&bin.join("zlib1.dll")),
+            Some(vec![
+                "KERNEL32.dll".to_owned(),
+                "libcrypto-3-x64.dll".to_owned(),
+                "VCRUNTIME140.dll".to_owned(),
+                "cudart64_12.dll".to_owned(),
+            ])
+        );
+
+        let lib = ::Config::new()
+            .cargo_metadata(false)
+            .find_package("zlib")
+            .unwrap();
+        assert_eq!(
+            lib.found_dlls,
+            vec![bin.join("zlib1.dll"), bin.join("libcrypto-3-x64.dll")]
+        );
+        assert_eq!(lib.unresolved_dlls, vec!["cudart64_12.dll".to_owned()]);
+        assert!(lib
+            .cargo_metadata
+            .iter()
+            .any(|m| m.starts_with("cargo:warning=") && m.contains("cudart64_12.dll")));
+        assert!(tmp_dir.path().join("libcrypto-3-x64.dll").exists());
+
+        assert!(is_system_dll("vcruntime140.dll"));
+        assert!(is_system_dll("api-ms-win-crt-heap-l1-1-0.dll"));
+        assert!(is_system_dll("mfplat.dll"));
+        // redistributable DLLs that only share a prefix with system DLLs
+        assert!(!is_system_dll("d3dx9_43.dll"));
+        assert!(!is_system_dll("mfc140u.dll"));
+        clean_env();
+    }
+
+    #[test]
+    fn dlls_are_copied_next_to_binaries() {
+        let _g = LOCK.lock();
+        clean_env();
+        let tmp_dir = tempdir().unwrap();
+        let root = tmp_dir.path().join("vcpkg");
+        write_test_tree(
+            &root,
+            "x64-windows",
+            &[("zlib", "1.2.11", "", &["lib/zlib.lib", "bin/zlib.dll"])],
+        );
+        let profile_dir = tmp_dir.path().join("target").join("debug");
+        let out_dir = profile_dir
+            .join("build")
+            .join("foo-sys-0123456789abcdef")
+            .join("out");
+        fs::create_dir_all(&out_dir).unwrap();
+        let staging = tmp_dir.path().join("staging");
+        env::set_var("VCPKG_ROOT", &root);
+        env::set_var("TARGET", "x86_64-pc-windows-msvc");
+        env::set_var("VCPKGRS_DYNAMIC", "1");
+        env::set_var("OUT_DIR", &out_dir);
+
+        ::Config::new()
+            .cargo_metadata(false)
+            .copy_dlls_to_target_dir(true)
+            .dll_destination(&staging)
+            .find_package("zlib")
+            .unwrap();
+        for dir in &[&out_dir, &profile_dir, &profile_dir.join("deps"), &staging] {
+            assert!(
+                dir.join("zlib.dll").exists(),
+                "not copied to {}",
+                dir.display()
+            );
+        }
+
+        let tmp_dir = tempdir().unwrap();
+        let profile_dir = tmp_dir.path().join("release");
+        let out_dir = profile_dir
+            .join("build")
+            .join("foo-sys-0123456789abcdef")
+            .join("out");
+        fs::create_dir_all(&out_dir).unwrap();
+        env::set_var("OUT_DIR", &out_dir);
+        ::Config::new()
+            .cargo_metadata(false)
+            .find_package("zlib")
+            .unwrap();
+        assert!(out_dir.join("zlib.dll").exists());
+        assert!(!profile_dir.join("zlib.dll").exists());
+
+        assert!(binary_dirs_from_out_dir(tmp_dir.path()).is_empty());
+        clean_env();
+    }
+
+    #[test]
+    fn dlls_are_only_copied_when_changed() {
+        let tmp_dir = tempdir().unwrap();
+        let src = tmp_dir.path().join("zlib1.dll");
+        let dest = tmp_dir.path().join("out").join("zlib1.dll");
+        fs::create_dir_all(dest.parent().unwrap()).unwrap();
+        fs::write(&src, "version 1").unwrap();
+
+        assert_eq!(deploy_file(&src, &dest, DllCopyMode::Copy).unwrap(), true);
+        assert_eq!(deploy_file(&src, &dest, DllCopyMode::Copy).unwrap(), false);
+        fs::write(&src, "version 2").unwrap();
+        assert_eq!(deploy_file(&src, &dest, DllCopyMode::Copy).unwrap(), true);
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "version 2");
+
+        // a hard link is as good as a copy
+        assert_eq!(
+            deploy_file(&src, &dest, DllCopyMode::HardLink).unwrap(),
+            false
+        );
+        fs::remove_file(&dest).unwrap();
+        assert_eq!(
+            deploy_file(&src, &dest, DllCopyMode::HardLink).unwrap(),
+            true
+        );
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "version 2");
+
+        if cfg!(unix) {
+            // the hard link is identical, so it is only replaced once it is gone
+            assert_eq!(
+                deploy_file(&src, &dest, DllCopyMode::Symlink).unwrap(),
+                false
+            );
+            fs::remove_file(&dest).unwrap();
+            assert_eq!(
+                deploy_file(&src, &dest, DllCopyMode::Symlink).unwrap(),
+                true
+            );
+            assert_eq!(fs::read_link(&dest).unwrap(), src);
+            assert_eq!(
+                deploy_file(&src, &dest, DllCopyMode::Symlink).unwrap(),
+                false
+            );
+
+            // going back to copies replaces the link rather than writing through it
+            assert_eq!(deploy_file(&src, &dest, DllCopyMode::Copy).unwrap(), true);
+            assert!(!fs::symlink_metadata(&dest)
+                .unwrap()
+                .file_type()
+                .is_symlink());
+            assert_eq!(fs::read_to_string(&src).unwrap(), "version 2");
+        }
+
+        // a copy made because a link could not be created is not replaced until
+        // the DLL changes
+        fs::remove_file(&dest).unwrap();
+        fs::copy(&src, &dest).unwrap();
+        assert_eq!(
+            deploy_file(&src, &dest, DllCopyMode::Symlink).unwrap(),
+            false
+        );
+        fs::write(&src, "version 3").unwrap();
+        assert_eq!(
+            deploy_file(&src, &dest, DllCopyMode::Symlink).unwrap(),
+            true
+        );
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "version 3");
+    }
+
+    #[test]
+    fn dll_that_cannot_be_replaced_is_a_warning() {
+        let _g = LOCK.lock();
+        clean_env();
+        let tmp_dir = tempdir().unwrap();
+        let root = tmp_dir.path().join("vcpkg");
+        write_test_tree(
+            &root,
+            "x64-windows",
+            &[("zlib", "1.2.11", "", &["lib/zlib.lib", "bin/zlib.dll"])],
+        );
+        fs::write(root.join("installed/x64-windows/bin/zlib.dll"), "new").unwrap();
+        env::set_var("VCPKG_ROOT", &root);
+        env::set_var("TARGET", "x86_64-pc-windows-msvc");
+        env::set_var("VCPKGRS_DYNAMIC", "1");
+        env::set_var("OUT_DIR", tmp_dir.path());
+
+        // a directory in the way can't be removed, like a DLL that is in use
+        let in_the_way = tmp_dir.path().join("zlib.dll");
+        fs::create_dir_all(in_the_way.join("locked")).unwrap();
+
+        let lib = ::Config::new()
+            .cargo_metadata(false)
+            .find_package("zlib")
+            .unwrap();
+        assert!(lib
+            .cargo_metadata
+            .iter()
+            .any(|m| m.starts_with("cargo:warning=Can't replace")));
+        clean_env();
+    }
+
+    #[test]
+    fn pdbs_are_copied_with_dlls() {
+        let _g = LOCK.lock();
+        clean_env();
+        let tmp_dir = tempdir().unwrap();
+        let root = tmp_dir.path().join("vcpkg");
+        write_test_tree(
+            &root,
+            "x64-windows",
+            &[(
+                "zlib",
+                "1.2.11",
+                "",
+                &[
+                    "lib/zlib.lib",
+                    "bin/zlib.dll",
+                    "bin/zlib.pdb",
+                    "debug/bin/zlibd.dll",
+                    "debug/bin/zlibd.pdb",
+                ],
+            )],
+        );
+        env::set_var("VCPKG_ROOT", &root);
+        env::set_var("TARGET", "x86_64-pc-windows-msvc");
+        env::set_var("VCPKGRS_DYNAMIC", "1");
+
+        let out_dir = tempdir().unwrap();
+        env::set_var("OUT_DIR", out_dir.path());
+        let lib = ::Config::new()
+            .cargo_metadata(false)
+            .find_package("zlib")
+            .unwrap();
+        assert!(lib.found_pdbs.is_empty());
+        assert!(!out_dir.path().join("zlib.pdb").exists());
+
+        let lib = ::Config::new()
+            .cargo_metadata(false)
+            .copy_pdbs(true)
+            .find_package("zlib")
+            .unwrap();
+        assert_eq!(
+            lib.found_pdbs,
+            vec![root.join("installed/x64-windows/bin/zlib.pdb")]
+        );
+        assert!(out_dir.path().join("zlib.pdb").exists());
+        assert!(!out_dir.path().join("zlibd.pdb").exists());
+
+        // the debug DLLs are not linked to, even in debug builds
+        env::set_var("PROFILE", "debug");
+        ::Config::new()
+            .cargo_metadata(false)
+            .copy_pdbs(true)
+            .find_package("zlib")
+            .unwrap();
+        assert!(!out_dir.path().join("zlibd.pdb").exists());
+        clean_env();
+    }
+
+    #[test]
+    fn sha256_test_vectors() {
+        let hex =
+            |data: &[u8]| -> String { sha256(data).iter().map(|b| format!("{:02x}", b)).collect() };
+        assert_eq!(
+            hex(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            hex(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            hex(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+
+        // files are hashed in chunks that don't line up with the blocks
+        let tmp_dir = tempdir().unwrap();
+        let path = tmp_dir.path().join("a.dll");
+        fs::write(&path, vec![b'a'; 1000000]).unwrap();
+        assert_eq!(
+            sha256_file(&path),
+            Some("cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0".to_owned())
+        );
+    }
+
+    #[test]
+    fn deployment_manifest_and_deploy() {
+        let _g = LOCK.lock();
+        clean_env();
+        let tmp_dir = tempdir().unwrap();
+        let root = tmp_dir.path().join("vcpkg");
+        write_test_tree(
+            &root,
+            "x64-windows",
+            &[
+                (
+                    "zlib",
+                    "1.2.11",
+                    "",
+                    &["lib/zlib.lib", "bin/zlib.dll", "bin/zlib.pdb"],
+                ),
+                ("openssl", "3.0.8", "", &["bin/libcrypto.dll"]),
+            ],
+        );
+        let bin = root.join("installed/x64-windows/bin");
+        fs::write(bin.join("zlib.dll"), pe_file(&["libcrypto.dll"], &[])).unwrap();
+        fs::write(bin.join("zlib.pdb"), "abc").unwrap();
+        env::set_var("VCPKG_ROOT", &root);
+        env::set_var("TARGET", "x86_64-pc-windows-msvc");
+        env::set_var("VCPKGRS_DYNAMIC", "1");
+        let out_dir = tempdir().unwrap();
+        env::set_var("OUT_DIR", out_dir.path());
+
+        let lib = ::Config::new()
+            .cargo_metadata(false)
+            .copy_pdbs(true)
+            .deployment_manifest(true)
+            .find_package("zlib")
+            .unwrap();
+        assert_eq!(lib.dll_ports[&bin.join("zlib.dll")], "zlib");
+        assert_eq!(lib.dll_ports[&bin.join("libcrypto.dll")], "openssl");
+
+        let manifest =
+            fs::read_to_string(out_dir.path().join("vcpkg-deployment-zlib.json")).unwrap();
+        let manifest = parse_json(&manifest).unwrap();
+        assert_eq!(
+            manifest.get("triplet").and_then(|v| v.as_str()),
+            Some("x64-windows")
+        );
+        let files = match manifest.get("files") {
+            Some(&JsonValue::Array(ref files)) => files.clone(),
+            _ => panic!("no files"),
+        };
+        let field = |i: usize, key: &str| {
+            files[i]
+                .get(key)
+                .and_then(|v| v.as_str())
+                .map(|v| v.to_owned())
+        };
+        assert_eq!(files.len(), 3);
+        assert_eq!(field(0, "file"), Some("zlib.dll".to_owned()));
+        assert_eq!(field(1, "file"), Some("libcrypto.dll".to_owned()));
+        assert_eq!(field(1, "port"), Some("openssl".to_owned()));
+        assert_eq!(field(2, "file"), Some("zlib.pdb".to_owned()));
+        assert_eq!(field(2, "port"), Some("zlib".to_owned()));
+        assert_eq!(
+            field(2, "sha256"),
+            Some("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad".to_owned())
+        );
+        assert_eq!(
+            field(1, "source"),
+            Some(bin.join("libcrypto.dll").to_string_lossy().into_owned())
+        );
+
+        let staging = tmp_dir.path().join("staging");
+        let deployed = lib.deploy(&staging).unwrap();
+        assert_eq!(deployed.len(), 3);
+        for name in &["zlib.dll", "libcrypto.dll", "zlib.pdb"] {
+            assert!(staging.join(name).exists());
+        }
+        clean_env();
+    }
+
+    #[test]
+    fn manual_link_libraries() {
+        let _g = LOCK.lock();
+        clean_env();
+        let tmp_dir = tempdir().unwrap();
+        let root = tmp_dir.path().join("vcpkg");
+        write_test_tree(
+            &root,
+            "x64-linux",
+            &[(
+                "sdl2",
+                "2.28.5",
+                "",
+                &[
+                    "lib/libSDL2.a",
+                    "lib/manual-link/libSDL2main.a",
+                    "debug/lib/manual-link/libSDL2maind.a",
+                ],
+            )],
+        );
+        env::set_var("VCPKG_ROOT", &root);
+        env::set_var("TARGET", "x86_64-unknown-linux-gnu");
+        env::set_var("OUT_DIR", tmp_dir.path());
+
+        // not linked unless asked for
+        let lib = ::Config::new()
+            .cargo_metadata(false)
+            .find_package("sdl2")
+            .unwrap();
+        assert_eq!(lib.found_names, vec!["SDL2"]);
+
+        let lib = ::Config::new()
+            .cargo_metadata(false)
+            .manual_link(&["SDL2main"])
+            .find_package("sdl2")
+            .unwrap();
+        assert_eq!(lib.found_names, vec!["SDL2main", "SDL2"]);
+        let dir = root.join("installed/x64-linux/lib/manual-link");
+        assert!(lib.link_paths.contains(&dir));
+        assert!(lib
+            .cargo_metadata
+            .contains(&format!("cargo:rustc-link-search=native={}", dir.display())));
+        assert!(lib
+            .cargo_metadata
+            .contains(&"cargo:rustc-link-lib=SDL2main".to_owned()));
+
+        assert!(match ::Config::new()
+            .cargo_metadata(false)
+            .manual_link(&["gtest_main"])
+            .find_package("sdl2")
+        {
+            Err(Error::LibNotFound(_)) => true,
+            _ => false,
+        });
+
+        // a debug library is not linked into a release build
+        assert!(match ::Config::new()
+            .cargo_metadata(false)
+            .manual_link(&["SDL2maind"])
+            .find_package("sdl2")
+        {
+            Err(Error::LibNotFound(_)) => true,
+            _ => false,
+        });
+        clean_env();
+    }
+
+    #[test]
+    fn ports_from_overlay_installed_roots() {
+        let _g = LOCK.lock();
+        clean_env();
+        let tmp_dir = tempdir().unwrap();
+        let root = tmp_dir.path().join("vcpkg");
+        write_test_tree(
+            &root,
+            "x64-linux",
+            &[
+                ("zlib", "1.2.13", "", &["lib/libz.a"]),
+                ("bzip2", "1.0.8", "", &["lib/libbz2.a"]),
+            ],
+        );
+        let internal = tmp_dir.path().join("internal");
+        write_test_tree(
+            &internal,
+            "x64-linux",
+            &[
+                (
+                    "acme",
+                    "2.0",
+                    "zlib, bzip2",
+                    &["lib/libacme.a", "include/acme.h"],
+                ),
+                ("zlib", "1.3", "", &["lib/libz.a"]),
+            ],
+        );
+        env::set_var("VCPKG_ROOT", &root);
+        env::set_var("TARGET", "x86_64-unknown-linux-gnu");
+        env::set_var("OUT_DIR", tmp_dir.path());
+
+        assert!(
+            match ::Config::new().cargo_metadata(false).find_package("acme") {
+                Err(Error::LibNotFound(_)) => true,
+                _ => false,
+            }
+        );
+
+        let main_root = root.join("installed");
+        let overlay_root = internal.join("installed");
+        let lib = ::Config::new()
+            .cargo_metadata(false)
+            .overlay_installed_root(overlay_root.clone())
+            .find_package("acme")
+            .unwrap();
+
+        // the overlay is searched first, so its zlib is used
+        let overlay_lib = overlay_root.join("x64-linux").join("lib");
+        let main_lib = main_root.join("x64-linux").join("lib");
+        assert!(lib.found_libs.contains(&overlay_lib.join("libacme.a")));
+        assert!(lib.found_libs.contains(&overlay_lib.join("libz.a")));
+        assert!(lib.found_libs.contains(&main_lib.join("libbz2.a")));
+        assert_eq!(lib.link_paths, vec![overlay_lib, main_lib]);
+        assert_eq!(lib.port_info["zlib"].version, "1.3");
+        assert_eq!(lib.port_info["zlib"].installed_root, overlay_root);
+        assert_eq!(lib.port_info["bzip2"].installed_root, main_root);
+        assert_eq!(lib.headers["acme"].len(), 1);
+
+        let warnings: Vec<&String> = lib
+            .cargo_metadata
+            .iter()
+            .filter(|l| l.starts_with("cargo:warning="))
+            .collect();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("package zlib"));
+        assert!(warnings[0].contains(&format!("{} (1.3)", overlay_root.display())));
+        assert!(warnings[0].contains(&format!("{} (1.2.13)", main_root.display())));
+
+        // the same from the environment
+        env::set_var("VCPKGRS_OVERLAY_INSTALLED_ROOTS", &overlay_root);
+        let lib = ::Config::new()
+            .cargo_metadata(false)
+            .find_package("acme")
+            .unwrap();
+        assert_eq!(lib.port_info["acme"].installed_root, overlay_root);
+        clean_env();
+    }
+
+    #[test]
+    fn installed_files_are_verified() {
+        let _g = LOCK.lock();
+        clean_env();
+        let tmp_dir = tempdir().unwrap();
+        let root = tmp_dir.path().join("vcpkg");
+        write_test_tree(
+            &root,
+            "x64-linux",
+            &[
+                ("zlib", "1.2.13", "", &["lib/libz.a", "include/zlib.h"]),
+                (
+                    "libpng",
+                    "1.6.37",
+                    "zlib",
+                    &["lib/libpng16.a", "include/png.h", "share/libpng/copyright"],
+                ),
+            ],
+        );
+        let installed = root.join("installed/x64-linux");
+        for file in &[
+            "lib/libz.a",
+            "include/zlib.h",
+            "lib/libpng16.a",
+            "include/png.h",
+            "share/libpng/copyright",
+        ] {
+            fs::write(installed.join(file), "contents").unwrap();
+        }
+        env::set_var("VCPKG_ROOT", &root);
+        env::set_var("TARGET", "x86_64-unknown-linux-gnu");
+        env::set_var("OUT_DIR", tmp_dir.path());
+
+        let problems = ::Config::new().verify("libpng").unwrap();
+        assert_eq!(problems.len(), 2);
+        assert!(problems.values().all(|p| p.is_empty()));
+
+        fs::remove_file(installed.join("include/png.h")).unwrap();
+        fs::write(installed.join("include/zlib.h"), "").unwrap();
+
+        let problems = ::Config::new().verify("libpng").unwrap();
+        assert_eq!(
+            problems["libpng"],
+            vec![FileProblem::Missing(installed.join("include/png.h"))]
+        );
+        assert_eq!(
+            problems["zlib"],
+            vec![FileProblem::Empty(installed.join("include/zlib.h"))]
+        );
+
+        // the libraries are still there, so this is only noticed when asked for
+        let lib = ::Config::new()
+            .cargo_metadata(false)
+            .find_package("libpng")
+            .unwrap();
+        assert!(lib.file_problems.is_empty());
+        match ::Config::new()
+            .cargo_metadata(false)
+            .verify_installation(true)
+            .find_package("libpng")
+        {
+            Err(Error::VcpkgInstallation(ref message)) => {
+                assert!(message.contains("libpng: "));
+                assert!(message.contains("png.h is missing"));
+                assert!(!message.contains("zlib.h"));
+            }
+            _ => panic!("damaged tree was not reported"),
+        }
+
+        // empty files are only warnings
+        fs::write(installed.join("include/png.h"), "contents").unwrap();
+        let lib = ::Config::new()
+            .cargo_metadata(false)
+            .verify_installation(true)
+            .find_package("libpng")
+            .unwrap();
+        assert_eq!(
+            lib.file_problems["zlib"],
+            vec![FileProblem::Empty(installed.join("include/zlib.h"))]
+        );
+        assert!(lib
+            .cargo_metadata
+            .iter()
+            .any(|m| m.starts_with("cargo:warning=zlib: ") && m.ends_with("zlib.h is empty")));
+        clean_env();
+    }
+
+    #[test]
+    fn abi_changes_are_tracked() {
+        let _g = LOCK.lock();
+        clean_env();
+        let tmp_dir = tempdir().unwrap();
+        let root = tmp_dir.path().join("vcpkg");
+        write_test_tree(
+            &root,
+            "x64-linux",
+            &[
+                ("libpng", "1.6.37", "zlib", &["lib/libpng16.a"]),
+                ("zlib", "1.2.11", "", &["lib/libz.a"]),
+            ],
+        );
+        let update = root.join("installed/vcpkg/updates/000001");
+        let write_libpng = |abi: &str| {
+            fs::write(
+                &update,
+                format!(
+                    "Package: libpng\nVersion: 1.6.37\nDepends: zlib\n\
+                     Architecture: x64-linux\nMulti-Arch: same\nAbi: {}\n\
+                     Status: install ok installed\n",
+                    abi
+                ),
+            )
+            .unwrap();
+        };
+        write_libpng("4f2e9a");
+        env::set_var("VCPKG_ROOT", &root);
+        env::set_var("TARGET", "x86_64-unknown-linux-gnu");
+        env::set_var("OUT_DIR", tmp_dir.path());
+
+        let lib = ::Config::new()
+            .cargo_metadata(false)
+            .find_package("libpng")
+            .unwrap();
+        assert!(!lib
+            .cargo_metadata
+            .iter()
+            .any(|l| l.starts_with("cargo:rustc-env=")));
+        let fingerprint = lib.abi_fingerprint();
+        assert_eq!(fingerprint.len(), 64);
+
+        let lib = ::Config::new()
+            .cargo_metadata(false)
+            .track_abi(true)
+            .find_package("libpng")
+            .unwrap();
+        assert!(lib
+            .cargo_metadata
+            .contains(&"cargo:rustc-env=VCPKG_ABI_LIBPNG=4f2e9a".to_owned()));
+        // zlib has no ABI hash, so the version stands in for it
+        assert!(lib
+            .cargo_metadata
+            .contains(&"cargo:rustc-env=VCPKG_ABI_ZLIB=1.2.11".to_owned()));
+        assert!(lib
+            .cargo_metadata
+            .contains(&format!("cargo:rustc-env=VCPKG_ABI={}", fingerprint)));
+        assert!(lib.cargo_metadata.contains(&format!(
+            "cargo:rerun-if-changed={}",
+            root.join("installed/vcpkg/updates").display()
+        )));
+        let summary = tmp_dir.path().join("vcpkg-abi-libpng.txt");
+        assert_eq!(
+            fs::read_to_string(&summary).unwrap(),
+            "triplet\tx64-linux\nlibpng\t1.6.37\t4f2e9a\nzlib\t1.2.11\t-\n"
+        );
+
+        // rebuilt with the same version
+        write_libpng("90c3d1b7");
+        let lib = ::Config::new()
+            .cargo_metadata(false)
+            .track_abi(true)
+            .find_package("libpng")
+            .unwrap();
+        assert!(lib.abi_fingerprint() != fingerprint);
+        assert!(lib
+            .cargo_metadata
+            .contains(&"cargo:rustc-env=VCPKG_ABI_LIBPNG=90c3d1b7".to_owned()));
+        assert!(fs::read_to_string(&summary).unwrap().contains("90c3d1b7"));
+        clean_env();
+    }
+
+    #[test]
+    fn crates_finding_ports_differently_are_reported() {
+        let _g = LOCK.lock();
+        clean_env();
+        let tmp_dir = tempdir().unwrap();
+        let old_root = tmp_dir.path().join("old-vcpkg");
+        write_test_tree(
+            &old_root,
+            "x64-linux",
+            &[("zlib", "1.2.11", "", &["lib/libz.a"])],
+        );
+        let new_root = tmp_dir.path().join("new-vcpkg");
+        write_test_tree(
+            &new_root,
+            "x64-linux",
+            &[
+                ("libpng", "1.6.37", "zlib", &["lib/libpng16.a"]),
+                ("zlib", "1.2.13", "", &["lib/libz.a"]),
+            ],
+        );
+        let profile_dir = tmp_dir.path().join("target").join("debug");
+        let out_dir = |name: &str| {
+            let dir = profile_dir.join("build").join(name).join("out");
+            fs::create_dir_all(&dir).unwrap();
+            dir
+        };
+        let a_out_dir = out_dir("libz-sys-0123456789abcdef");
+        let b_out_dir = out_dir("libpng-sys-fedcba9876543210");
+        let c_out_dir = out_dir("libz-sys-00000000deadbeef");
+        env::set_var("TARGET", "x86_64-unknown-linux-gnu");
+
+        let find = |crate_name: &str, out_dir: &Path, root: &Path, port: &str| {
+            env::set_var("CARGO_PKG_NAME", crate_name);
+            env::set_var("OUT_DIR", out_dir);
+            env::set_var("VCPKG_ROOT", root);
+            ::Config::new().cargo_metadata(false).find_package(port)
+        };
+        let warnings = |lib: &Library| -> Vec<String> {
+            lib.cargo_metadata
+                .iter()
+                .filter(|l| l.starts_with("cargo:warning="))
+                .cloned()
+                .collect()
+        };
+
+        let lib = find("libz-sys", &a_out_dir, &old_root, "zlib").unwrap();
+        assert!(warnings(&lib).is_empty());
+        // finding the same again is fine
+        let lib = find("libz-sys", &a_out_dir, &old_root, "zlib").unwrap();
+        assert!(warnings(&lib).is_empty());
+
+        let lib = find("libpng-sys", &b_out_dir, &new_root, "libpng").unwrap();
+        let lib_warnings = warnings(&lib);
+        assert_eq!(lib_warnings.len(), 1);
+        assert!(lib_warnings[0].contains("package zlib was found by libpng-sys"));
+        assert!(lib_warnings[0].contains("and by libz-sys"));
+        assert!(lib_warnings[0].contains(&old_root.display().to_string()));
+        assert!(lib_warnings[0].contains(&new_root.display().to_string()));
+        let record = fs::read_to_string(profile_dir.join("vcpkg-rs-resolved-ports.txt")).unwrap();
+        assert_eq!(record.lines().count(), 3);
+        assert!(!profile_dir.join("vcpkg-rs-resolved-ports.lock").exists());
+
+        env::set_var("VCPKGRS_CONSISTENCY", "error");
+        match find("libz-sys", &a_out_dir, &old_root, "zlib") {
+            Err(Error::Inconsistent(_)) => {}
+            _ => panic!("inconsistent build was not an error"),
         }
+
+        // once both use the same tree, the build is consistent again
+        let lib = find("libz-sys", &a_out_dir, &new_root, "zlib").unwrap();
+        assert!(warnings(&lib).is_empty());
+
+        // what an earlier build of a crate found, for example with other features,
+        // is dropped when the crate is built again
+        fs::remove_file(profile_dir.join("vcpkg-rs-resolved-ports.txt")).unwrap();
+        find("libz-sys", &a_out_dir, &old_root, "zlib").unwrap();
+        let lib = find("libz-sys", &c_out_dir, &new_root, "zlib").unwrap();
+        assert!(warnings(&lib).is_empty());
+        let lib = find("libpng-sys", &b_out_dir, &new_root, "libpng").unwrap();
+        assert!(warnings(&lib).is_empty());
+        let record = fs::read_to_string(profile_dir.join("vcpkg-rs-resolved-ports.txt")).unwrap();
+        assert!(!record.contains(&a_out_dir.display().to_string()));
+
+        // a lock left behind by a build script that was killed is taken over
+        let lock = profile_dir.join("vcpkg-rs-resolved-ports.lock");
+        File::create(&lock).unwrap();
+        thread::sleep(Duration::from_millis(2100));
+        find("libpng-sys", &b_out_dir, &new_root, "libpng").unwrap();
+        assert!(!lock.exists());
+
+        env::set_var("VCPKGRS_CONSISTENCY", "off");
+        fs::remove_file(profile_dir.join("vcpkg-rs-resolved-ports.txt")).unwrap();
+        find("libz-sys", &a_out_dir, &old_root, "zlib").unwrap();
+        assert!(!profile_dir.join("vcpkg-rs-resolved-ports.txt").exists());
+        clean_env();
     }
 
     #[test]
@@ -1679,6 +6083,370 @@ mod tests {
         }
     }
 
+    #[test]
+    fn persistent_port_cache() {
+        let _g = LOCK.lock();
+        clean_env();
+        env::set_var("VCPKG_ROOT", vcpkg_test_tree_loc("normalized"));
+        env::set_var("TARGET", "i686-pc-windows-msvc");
+        env::set_var("VCPKGRS_DYNAMIC", "1");
+        let tmp_dir = tempdir().unwrap();
+        env::set_var("OUT_DIR", tmp_dir.path());
+
+        let lib = ::Config::new()
+            .persistent_cache(true)
+            .find_package("harfbuzz")
+            .unwrap();
+
+        let status_path = vcpkg_test_tree_loc("normalized")
+            .join("installed")
+            .join("vcpkg");
+        let mut target =
+            find_vcpkg_target(&::Config::new(), &TargetTriplet::from("x86-windows")).unwrap();
+        let cache_file = port_cache_file(&target, tmp_dir.path());
+        let mut cached = read_port_cache(&cache_file, &status_path).unwrap();
+        assert!(!cached.stamp.is_empty());
+        for port in &lib.ports {
+            assert!(cached.ports.contains_key(port));
+        }
+        assert_eq!(
+            cached.ports["harfbuzz"].deps,
+            vec!["freetype", "ragel", "icu"]
+        );
+
+        // a cache written for another tree must not be used
+        assert!(read_port_cache(&cache_file, &tmp_dir.path()).is_none());
+
+        // a second lookup is served from the cache and gives the same answer
+        PORT_CACHE.with(|cache| cache.borrow_mut().clear());
+        let again = ::Config::new()
+            .persistent_cache(true)
+            .find_package("harfbuzz")
+            .unwrap();
+        assert_eq!(lib.ports, again.ports);
+        assert_eq!(lib.found_libs, again.found_libs);
+
+        // which is read from the file rather than the status database
+        let marker = cached.ports["harfbuzz"].clone();
+        cached
+            .ports
+            .insert("from-the-cache-file".to_owned(), marker);
+        write_port_cache(&cache_file, &status_path, &cached).unwrap();
+        PORT_CACHE.with(|cache| cache.borrow_mut().clear());
+        let ports = load_ports_cached(&target, true).unwrap();
+        assert!(ports.contains_key("from-the-cache-file"));
+
+        // installed directories with the same triplet have files of their own
+        target.status_path = tmp_dir.path().join("overlay").join("vcpkg");
+        assert!(port_cache_file(&target, tmp_dir.path()) != cache_file);
+        clean_env();
+    }
+
+    #[test]
+    fn unrelated_broken_port_is_ignored() {
+        let _g = LOCK.lock();
+        clean_env();
+        let tmp_dir = tempdir().unwrap();
+        write_test_tree(
+            tmp_dir.path(),
+            "x64-linux",
+            &[
+                ("zlib", "1.2.11", "", &["lib/libz.a", "include/zlib.h"]),
+                // installed, but its .list file has gone missing
+                ("broken", "1.0", "", &[]),
+            ],
+        );
+        env::set_var("VCPKG_ROOT", tmp_dir.path());
+        env::set_var("TARGET", "x86_64-unknown-linux-gnu");
+        env::set_var("OUT_DIR", tmp_dir.path());
+
+        let lib = ::find_package("zlib").unwrap();
+        assert_eq!(lib.ports, vec!["zlib"]);
+        assert_eq!(lib.found_names, vec!["z"]);
+
+        assert!(match ::find_package("broken") {
+            Err(Error::VcpkgInstallation(_)) => true,
+            _ => false,
+        });
+        clean_env();
+    }
+
+    #[test]
+    fn mingw_triplets() {
+        let _g = LOCK.lock();
+        clean_env();
+        let tmp_dir = tempdir().unwrap();
+        write_test_tree(
+            tmp_dir.path(),
+            "x64-mingw-dynamic",
+            &[(
+                "zlib",
+                "1.2.11",
+                "",
+                &["bin/libzlib1.dll", "lib/libzlib.dll.a", "include/zlib.h"],
+            )],
+        );
+        env::set_var("VCPKG_ROOT", tmp_dir.path());
+        env::set_var("TARGET", "x86_64-pc-windows-gnu");
+        env::set_var("VCPKGRS_DYNAMIC", "1");
+        env::set_var("OUT_DIR", tmp_dir.path());
+        // the MinGW runtime comes with the toolchain rather than the vcpkg tree
+        fs::write(
+            tmp_dir
+                .path()
+                .join("installed/x64-mingw-dynamic/bin/libzlib1.dll"),
+            pe_file(
+                &[
+                    "KERNEL32.dll",
+                    "msvcrt.dll",
+                    "libgcc_s_seh-1.dll",
+                    "libwinpthread-1.dll",
+                ],
+                &[],
+            ),
+        )
+        .unwrap();
+
+        let lib = ::Config::new()
+            .cargo_metadata(false)
+            .find_package("zlib")
+            .unwrap();
+        assert_eq!(lib.vcpkg_triplet, "x64-mingw-dynamic");
+        assert!(!lib.is_static);
+        assert_eq!(lib.found_names, vec!["zlib"]);
+        assert!(lib.found_libs[0].ends_with("libzlib.dll.a"));
+        assert_eq!(lib.found_dlls.len(), 1);
+        assert!(lib.found_dlls[0].ends_with("libzlib1.dll"));
+        assert!(lib.unresolved_dlls.is_empty());
+        assert!(!lib
+            .cargo_metadata
+            .iter()
+            .any(|m| m.starts_with("cargo:warning=")));
+
+        let triplet = TargetTriplet::from("x64-mingw-static");
+        assert!(triplet.is_static);
+        assert_eq!(triplet.lib_stem_for("libzlib.a", true), Some("libzlib"));
+        assert_eq!(triplet.lib_stem_for("zlib.lib", true), None);
+        clean_env();
+    }
+
+    #[test]
+    fn dynamic_triplets_on_linux_and_macos() {
+        let _g = LOCK.lock();
+        clean_env();
+        let tmp_dir = tempdir().unwrap();
+        write_test_tree(
+            tmp_dir.path(),
+            "x64-linux-dynamic",
+            &[(
+                "zlib",
+                "1.2.11",
+                "",
+                &[
+                    "lib/libz.so",
+                    "lib/libz.so.1",
+                    "lib/libz.so.1.2.11",
+                    "include/zlib.h",
+                ],
+            )],
+        );
+        let out_dir = tempdir().unwrap();
+        env::set_var("VCPKG_ROOT", tmp_dir.path());
+        env::set_var("TARGET", "x86_64-unknown-linux-gnu");
+        env::set_var("VCPKGRS_TRIPLET", "x64-linux-dynamic");
+        env::set_var("VCPKGRS_DYNAMIC", "1");
+        env::set_var("OUT_DIR", out_dir.path());
+
+        let lib = ::find_package("zlib").unwrap();
+        let lib_dir = tmp_dir
+            .path()
+            .join("installed")
+            .join("x64-linux-dynamic")
+            .join("lib");
+        assert!(!lib.is_static);
+        assert!(lib
+            .cargo_metadata
+            .contains(&"cargo:rustc-link-lib=dylib=z".to_owned()));
+        assert_eq!(lib.found_libs, vec![lib_dir.join("libz.so")]);
+        assert_eq!(lib.found_dlls.len(), 3);
+        assert!(lib.found_dlls.contains(&lib_dir.join("libz.so.1.2.11")));
+        assert_eq!(lib.rpaths, vec![lib_dir.clone()]);
+        // the libraries are loaded from the vcpkg tree, so they are not copied
+        assert!(!out_dir.path().join("libz.so.1").exists());
+
+        let osx = TargetTriplet::from("arm64-osx-dynamic");
+        assert!(!osx.is_static);
+        assert_eq!(osx.lib_stem_for("libz.dylib", false), Some("libz"));
+        assert_eq!(osx.lib_stem_for("libz.1.dylib", false), None);
+        assert!(osx.is_runtime_lib("libz.1.2.11.dylib"));
+        clean_env();
+    }
+
+    #[test]
+    fn triplet_file_settings() {
+        let tmp_dir = tempdir().unwrap();
+        let triplets = tmp_dir.path().join("triplets");
+        fs::create_dir_all(&triplets).unwrap();
+        File::create(triplets.join("x64-windows.cmake"))
+            .unwrap()
+            .write_all(
+                b"set(VCPKG_TARGET_ARCHITECTURE x64)\n\
+                  set(VCPKG_CRT_LINKAGE dynamic)\n\
+                  set(VCPKG_LIBRARY_LINKAGE dynamic)\n",
+            )
+            .unwrap();
+        File::create(triplets.join("x64-windows-static-md-v143.cmake"))
+            .unwrap()
+            .write_all(
+                b"include(${CMAKE_CURRENT_LIST_DIR}/x64-windows.cmake)\n\
+                  set(VCPKG_LIBRARY_LINKAGE \"static\") # not dynamic\n\
+                  if(PORT MATCHES \"qt5-.*\")\n\
+                  set(VCPKG_LIBRARY_LINKAGE dynamic)\n\
+                  endif()\n\
+                  set(VCPKG_BUILD_TYPE release)\n\
+                  set(VCPKG_PLATFORM_TOOLSET v143)\n",
+            )
+            .unwrap();
+
+        let path = find_triplet_file(tmp_dir.path(), "x64-windows-static-md-v143").unwrap();
+        let settings = TripletSettings::load(&path).unwrap();
+        assert_eq!(settings.target_architecture, Some("x64".to_owned()));
+        assert_eq!(settings.crt_linkage, Some("dynamic".to_owned()));
+        assert_eq!(settings.library_linkage, Some("static".to_owned()));
+        assert_eq!(settings.cmake_system_name, None);
+        assert!(!settings.has_debug());
+        assert!(settings.skipped_includes.is_empty());
+
+        // includes that can't be followed are skipped
+        File::create(triplets.join("x64-windows-custom.cmake"))
+            .unwrap()
+            .write_all(
+                b"include(${CMAKE_CURRENT_LIST_DIR}/missing.cmake)\n\
+                  include(${VCPKG_ROOT_DIR}/triplets/x64-windows.cmake)\n\
+                  set(VCPKG_CRT_LINKAGE static)\n",
+            )
+            .unwrap();
+        let settings = TripletSettings::load(&triplets.join("x64-windows-custom.cmake")).unwrap();
+        assert_eq!(settings.crt_linkage, Some("static".to_owned()));
+        assert_eq!(settings.library_linkage, None);
+        assert_eq!(
+            settings.skipped_includes,
+            vec![
+                "${CMAKE_CURRENT_LIST_DIR}/missing.cmake".to_owned(),
+                "${VCPKG_ROOT_DIR}/triplets/x64-windows.cmake".to_owned(),
+            ]
+        );
+
+        // the name suggests static linkage, but the file says otherwise
+        let mut triplet = TargetTriplet::from("x64-windows-static-custom");
+        TripletSettings::load(&triplets.join("x64-windows.cmake"))
+            .unwrap()
+            .apply(&mut triplet);
+        assert!(!triplet.is_static);
+        assert_eq!(triplet.lib_suffix, "lib");
+    }
+
+    #[test]
+    fn overlay_triplet_decides_linkage() {
+        let _g = LOCK.lock();
+        clean_env();
+        let tmp_dir = tempdir().unwrap();
+        write_test_tree(
+            tmp_dir.path(),
+            "x64-linux-shared",
+            &[("zlib", "1.2.11", "", &["lib/libz.so", "lib/libz.so.1"])],
+        );
+        let overlay = tempdir().unwrap();
+        File::create(overlay.path().join("x64-linux-shared.cmake"))
+            .unwrap()
+            .write_all(
+                b"include($ENV{TRIPLET_COMMON}/common.cmake)\n\
+                  set(VCPKG_TARGET_ARCHITECTURE x64)\n\
+                  set(VCPKG_CRT_LINKAGE dynamic)\n\
+                  set(VCPKG_LIBRARY_LINKAGE dynamic)\n\
+                  set(VCPKG_CMAKE_SYSTEM_NAME Linux)\n",
+            )
+            .unwrap();
+        env::set_var("VCPKG_ROOT", tmp_dir.path());
+        env::set_var("VCPKG_OVERLAY_TRIPLETS", overlay.path());
+        env::set_var("TARGET", "x86_64-unknown-linux-gnu");
+        env::set_var("VCPKGRS_TRIPLET", "x64-linux-shared");
+        env::set_var("VCPKGRS_DYNAMIC", "1");
+        env::set_var("OUT_DIR", tmp_dir.path());
+
+        let lib = ::find_package("zlib").unwrap();
+        assert!(!lib.is_static);
+        assert!(lib
+            .cargo_metadata
+            .contains(&"cargo:rustc-link-lib=dylib=z".to_owned()));
+        // the include is only known to cmake, so it is skipped with a warning
+        assert!(lib.cargo_metadata.iter().any(|m| m.starts_with(
+            "cargo:warning=Could not follow include($ENV{TRIPLET_COMMON}/common.cmake)"
+        )));
+        clean_env();
+    }
+
+    #[test]
+    fn per_port_linkage() {
+        let _g = LOCK.lock();
+        clean_env();
+        let tmp_dir = tempdir().unwrap();
+        write_test_tree(
+            tmp_dir.path(),
+            "x64-windows-static-md",
+            &[
+                ("zlib", "1.2.11", "", &["lib/zlib.lib", "include/zlib.h"]),
+                (
+                    "qt5-base",
+                    "5.15.2",
+                    "zlib, qt5-header-only",
+                    &["bin/Qt5Core.dll", "lib/Qt5Core.lib"],
+                ),
+                ("qt5-header-only", "5.15.2", "", &["include/qt5.h"]),
+            ],
+        );
+        let triplets = tmp_dir.path().join("triplets").join("community");
+        fs::create_dir_all(&triplets).unwrap();
+        File::create(triplets.join("x64-windows-static-md.cmake"))
+            .unwrap()
+            .write_all(
+                b"set(VCPKG_TARGET_ARCHITECTURE x64)\n\
+                  set(VCPKG_CRT_LINKAGE dynamic)\n\
+                  set(VCPKG_LIBRARY_LINKAGE static)\n\
+                  if(PORT MATCHES \"qt5-.*\")\n\
+                  set(VCPKG_LIBRARY_LINKAGE dynamic)\n\
+                  else()\n\
+                  set(VCPKG_LIBRARY_LINKAGE static)\n\
+                  endif()\n",
+            )
+            .unwrap();
+        // the linkage inside if() only applies to some ports
+        let settings =
+            TripletSettings::load(&triplets.join("x64-windows-static-md.cmake")).unwrap();
+        assert_eq!(settings.library_linkage, Some("static".to_owned()));
+
+        let out_dir = tempdir().unwrap();
+        env::set_var("VCPKG_ROOT", tmp_dir.path());
+        env::set_var("TARGET", "x86_64-pc-windows-msvc");
+        env::set_var("OUT_DIR", out_dir.path());
+
+        let lib = ::find_package("qt5-base").unwrap();
+        assert!(lib.is_static);
+        assert_eq!(lib.ports.len(), 3);
+        // the header-only port matches the triplet's rule, but has no DLLs
+        assert_eq!(lib.dynamic_ports, vec!["qt5-base"]);
+        assert_eq!(lib.found_libs.len(), 2);
+        assert_eq!(lib.found_dlls.len(), 1);
+        assert!(out_dir.path().join("Qt5Core.dll").exists());
+
+        // zlib on its own needs nothing at runtime
+        let lib = ::find_package("zlib").unwrap();
+        assert!(lib.dynamic_ports.is_empty());
+        assert!(lib.found_dlls.is_empty());
+        assert!(lib.dll_paths.is_empty());
+        clean_env();
+    }
+
     #[test]
     fn custom_target_triplet_in_config() {
         let _g = LOCK.lock();
@@ -1702,6 +6470,131 @@ mod tests {
         clean_env();
     }
 
+    #[test]
+    fn triplet_candidates_are_tried_in_order() {
+        let _g = LOCK.lock();
+        clean_env();
+        env::set_var("VCPKG_ROOT", vcpkg_test_tree_loc("normalized"));
+        env::set_var("TARGET", "x86_64-pc-windows-msvc");
+        let tmp_dir = tempdir().unwrap();
+        env::set_var("OUT_DIR", tmp_dir.path());
+
+        // x64-windows-static-md is not installed in the test tree
+        let candidates = ["x86-windows", "x64-windows", "x64-windows-static"];
+        env::set_var("CARGO_CFG_TARGET_FEATURE", "sse2");
+        let err = ::Config::new()
+            .triplet_candidates(&candidates)
+            .find_package("libmysql")
+            .unwrap_err();
+        match err {
+            Error::LibNotFound(ref detail) => {
+                assert!(detail.contains("x64-windows-static-md (not installed)"));
+                assert!(detail.contains("x86-windows (different architecture)"));
+                assert!(detail.contains("x64-windows (dynamic linkage needs VCPKGRS_DYNAMIC)"));
+                assert!(detail.contains("x64-windows-static (different C runtime linkage)"));
+            }
+            _ => panic!("unexpected error {:?}", err),
+        }
+
+        env::set_var("CARGO_CFG_TARGET_FEATURE", "crt-static");
+        let lib = ::Config::new()
+            .triplet_candidates(&candidates)
+            .find_package("libmysql")
+            .unwrap();
+        assert_eq!(lib.vcpkg_triplet, "x64-windows-static");
+
+        // harfbuzz is installed for x64-osx, but that can't be linked into a
+        // Windows program
+        env::remove_var("CARGO_CFG_TARGET_FEATURE");
+        env::set_var(
+            "VCPKGRS_TRIPLET_CANDIDATES",
+            "x64-windows-static-md, x64-osx",
+        );
+        match ::find_package("harfbuzz") {
+            Err(Error::LibNotFound(ref detail)) => {
+                assert!(detail.contains("x64-osx (different operating system)"))
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        // a candidate for the same OS is fine
+        env::set_var("TARGET", "x86_64-apple-darwin");
+        env::set_var("VCPKGRS_TRIPLET", "x64-osx-custom");
+        env::set_var("VCPKGRS_TRIPLET_CANDIDATES", "x64-windows-static, x64-osx");
+        let lib = ::find_package("harfbuzz").unwrap();
+        assert_eq!(lib.vcpkg_triplet, "x64-osx");
+        clean_env();
+    }
+
+    #[test]
+    fn find_tool_in_host_triplet() {
+        let _g = LOCK.lock();
+        clean_env();
+        let tmp_dir = tempdir().unwrap();
+        let root = tmp_dir.path().join("vcpkg");
+        write_test_tree(
+            &root,
+            "x64-windows",
+            &[
+                (
+                    "protobuf",
+                    "3.21.12",
+                    "",
+                    &[
+                        "tools/protobuf/protoc.exe",
+                        "tools/protobuf/libprotobuf.dll",
+                        "lib/libprotobuf.lib",
+                    ],
+                ),
+                ("ragel", "6.10", "", &["tools/ragel.exe"]),
+            ],
+        );
+        env::set_var("VCPKG_ROOT", &root);
+        env::set_var("OUT_DIR", tmp_dir.path());
+        env::set_var("HOST", "x86_64-pc-windows-msvc");
+        env::set_var("TARGET", "aarch64-pc-windows-msvc");
+
+        let mut config = ::Config::new();
+        config.cargo_metadata(false);
+        let protoc = config.find_tool("protobuf", "protoc").unwrap();
+        assert_eq!(
+            protoc,
+            root.join("installed/x64-windows/tools/protobuf/protoc.exe")
+        );
+        let ragel = config.find_tool("ragel", "ragel").unwrap();
+        assert_eq!(ragel, root.join("installed/x64-windows/tools/ragel.exe"));
+        assert!(match config.find_tool("protobuf", "libprotobuf") {
+            Err(Error::LibNotFound(_)) => true,
+            _ => false,
+        });
+
+        // arm64-windows is not installed
+        env::set_var("VCPKG_DEFAULT_HOST_TRIPLET", "arm64-windows");
+        assert!(match config.find_tool("protobuf", "protoc") {
+            Err(Error::LibNotFound(_)) => true,
+            _ => false,
+        });
+        clean_env();
+
+        let tmp_dir = tempdir().unwrap();
+        let root = tmp_dir.path().join("vcpkg");
+        write_test_tree(
+            &root,
+            "x64-linux",
+            &[("flatbuffers", "23.5.26", "", &["tools/flatbuffers/flatc"])],
+        );
+        env::set_var("VCPKG_ROOT", &root);
+        env::set_var("OUT_DIR", tmp_dir.path());
+        env::set_var("HOST", "x86_64-unknown-linux-gnu");
+        env::set_var("TARGET", "aarch64-linux-android");
+        let flatc = config.find_tool("flatbuffers", "flatc").unwrap();
+        assert_eq!(
+            flatc,
+            root.join("installed/x64-linux/tools/flatbuffers/flatc")
+        );
+        clean_env();
+    }
+
     #[test]
     fn custom_target_triplet_by_env_no_default() {
         let _g = LOCK.lock();
@@ -1746,6 +6639,104 @@ mod tests {
         clean_env();
     }
 
+    #[test]
+    fn triplet_detection_for_rust_targets() {
+        for &(target, expected) in &[
+            ("x86_64-pc-windows-msvc", "x64-windows-static-md"),
+            ("i686-pc-windows-msvc", "x86-windows-static-md"),
+            ("aarch64-pc-windows-msvc", "arm64-windows-static-md"),
+            ("thumbv7a-pc-windows-msvc", "arm-windows-static-md"),
+            ("x86_64-win7-windows-msvc", "x64-windows-static-md"),
+            ("x86_64-uwp-windows-msvc", "x64-uwp-static-md"),
+            ("x86_64-pc-windows-gnu", "x64-mingw-static"),
+            ("i686-pc-windows-gnu", "x86-mingw-static"),
+            ("aarch64-pc-windows-gnullvm", "arm64-mingw-static"),
+            ("x86_64-apple-darwin", "x64-osx"),
+            ("aarch64-apple-darwin", "arm64-osx"),
+            ("x86_64-unknown-linux-gnu", "x64-linux"),
+            ("aarch64-unknown-linux-gnu", "arm64-linux"),
+            ("i686-unknown-linux-gnu", "x86-linux"),
+            ("armv7-unknown-linux-gnueabihf", "arm-linux"),
+            ("riscv64gc-unknown-linux-gnu", "riscv64-linux"),
+            ("powerpc64le-unknown-linux-gnu", "ppc64le-linux"),
+            ("s390x-unknown-linux-gnu", "s390x-linux"),
+            ("loongarch64-unknown-linux-gnu", "loongarch64-linux"),
+            ("armv7-linux-androideabi", "arm-android"),
+            ("thumbv7neon-linux-androideabi", "arm-neon-android"),
+            ("aarch64-linux-android", "arm64-android"),
+            ("i686-linux-android", "x86-android"),
+            ("x86_64-linux-android", "x64-android"),
+            ("x86_64-unknown-freebsd", "x64-freebsd"),
+            ("x86_64-unknown-openbsd", "x64-openbsd"),
+            ("aarch64-apple-ios", "arm64-ios"),
+            ("x86_64-apple-ios", "x64-ios"),
+            ("aarch64-apple-ios-sim", "arm64-ios-simulator"),
+            ("wasm32-unknown-unknown", "wasm32-emscripten"),
+            ("wasm32-unknown-emscripten", "wasm32-emscripten"),
+            ("wasm32-wasi", "wasm32-emscripten"),
+        ] {
+            let rust_target = RustTarget::from_target_str(target);
+            match triplet_for_rust_target(&rust_target, false, false) {
+                Ok(triplet) => assert_eq!(triplet.triplet, expected, "for {}", target),
+                Err(e) => panic!("{} for {} ({:?})", e, target, rust_target),
+            }
+        }
+
+        for &(target, is_static, is_dynamic, expected) in &[
+            ("x86_64-pc-windows-msvc", true, false, "x64-windows-static"),
+            ("x86_64-pc-windows-msvc", false, true, "x64-windows"),
+            ("i686-pc-windows-msvc", true, true, "x86-windows-static"),
+            ("aarch64-pc-windows-msvc", false, true, "arm64-windows"),
+            ("x86_64-pc-windows-gnu", false, true, "x64-mingw-dynamic"),
+            ("x86_64-pc-windows-gnu", true, false, "x64-mingw-static"),
+            ("x86_64-unknown-linux-gnu", true, false, "x64-linux"),
+        ] {
+            let rust_target = RustTarget::from_target_str(target);
+            let triplet = triplet_for_rust_target(&rust_target, is_static, is_dynamic).unwrap();
+            assert_eq!(triplet.triplet, expected, "for {}", target);
+        }
+
+        for target in &[
+            "powerpc64-unknown-linux-gnu",
+            "sparc64-unknown-linux-gnu",
+            "x86_64-unknown-linux-musl",
+            "aarch64-unknown-linux-musl",
+            "",
+        ] {
+            let rust_target = RustTarget::from_target_str(target);
+            assert!(triplet_for_rust_target(&rust_target, false, false).is_err());
+        }
+        // there is no x64-uwp-static triplet
+        let rust_target = RustTarget::from_target_str("x86_64-uwp-windows-msvc");
+        assert!(triplet_for_rust_target(&rust_target, true, false).is_err());
+        assert_eq!(
+            triplet_for_rust_target(&rust_target, false, true)
+                .unwrap()
+                .triplet,
+            "x64-uwp"
+        );
+    }
+
+    #[test]
+    fn triplet_detection_prefers_cargo_cfg() {
+        let _g = LOCK.lock();
+        clean_env();
+        // TARGET is ignored when cargo has described the target
+        env::set_var("TARGET", "x86_64-pc-windows-msvc");
+        env::set_var("CARGO_CFG_TARGET_ARCH", "aarch64");
+        env::set_var("CARGO_CFG_TARGET_OS", "linux");
+        env::set_var("CARGO_CFG_TARGET_ENV", "gnu");
+        env::set_var("CARGO_CFG_TARGET_ENDIAN", "little");
+        assert_eq!(detect_target_triplet().unwrap().triplet, "arm64-linux");
+
+        env::set_var("CARGO_CFG_TARGET_ARCH", "arm");
+        env::set_var("CARGO_CFG_TARGET_OS", "android");
+        env::set_var("CARGO_CFG_TARGET_ENV", "");
+        env::set_var("CARGO_CFG_TARGET_FEATURE", "aclass,neon,thumb2,v7");
+        assert_eq!(detect_target_triplet().unwrap().triplet, "arm-neon-android");
+        clean_env();
+    }
+
     // #[test]
     // fn dynamic_build_package_specific_bailout() {
     //     clean_env();
@@ -1974,6 +6965,175 @@ mod tests {
         env::remove_var("VCPKGRS_DISABLE");
         env::remove_var("VCPKGRS_NO_LIBMYSQL");
         env::remove_var("VCPKGRS_TRIPLET");
+        env::remove_var("VCPKG_OVERLAY_TRIPLETS");
+        env::remove_var("VCPKGRS_TRIPLET_CANDIDATES");
+        env::remove_var("VCPKGRS_OVERLAY_INSTALLED_ROOTS");
+        env::remove_var("VCPKGRS_CONSISTENCY");
+        env::remove_var("CARGO_PKG_NAME");
+        env::remove_var("VCPKG_DEFAULT_HOST_TRIPLET");
+        env::remove_var("HOST");
+        env::remove_var("PROFILE");
+        env::remove_var("CARGO_CFG_TARGET_ARCH");
+        env::remove_var("CARGO_CFG_TARGET_OS");
+        env::remove_var("CARGO_CFG_TARGET_ENV");
+        env::remove_var("CARGO_CFG_TARGET_ABI");
+        env::remove_var("CARGO_CFG_TARGET_ENDIAN");
+    }
+
+    // Write a minimal vcpkg tree for `triplet` at `root`. Each port is given as
+    // (name, version, depends, files) with files relative to the triplet
+    // directory. An empty file list leaves the port without a .list file.
+    fn write_test_tree(root: &Path, triplet: &str, ports: &[(&str, &str, &str, &[&str])]) {
+        let installed = root.join("installed");
+        let info = installed.join("vcpkg").join("info");
+        fs::create_dir_all(&info).unwrap();
+        fs::create_dir_all(installed.join("vcpkg").join("updates")).unwrap();
+        File::create(root.join(".vcpkg-root")).unwrap();
+
+        let mut status = String::new();
+        for &(name, version, depends, files) in ports {
+            status.push_str(&format!("Package: {}\nVersion: {}\n", name, version));
+            if !depends.is_empty() {
+                status.push_str(&format!("Depends: {}\n", depends));
+            }
+            status.push_str(&format!(
+                "Architecture: {}\nMulti-Arch: same\nStatus: install ok installed\n\n",
+                triplet
+            ));
+
+            if files.is_empty() {
+                continue;
+            }
+            let mut list = format!("{}/\n", triplet);
+            for file in files {
+                let path = installed.join(triplet).join(file);
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                File::create(&path).unwrap();
+                list.push_str(&format!("{}/{}\n", triplet, file));
+            }
+            File::create(info.join(format!("{}_{}_{}.list", name, version, triplet)))
+                .unwrap()
+                .write_all(list.as_bytes())
+                .unwrap();
+        }
+        File::create(installed.join("vcpkg").join("status"))
+            .unwrap()
+            .write_all(status.as_bytes())
+            .unwrap();
+    }
+
+    // An ar archive of (name, contents) members, as used for .lib files.
+    fn ar_archive(members: &[(&str, Vec<u8>)]) -> Vec<u8> {
+        let mut data = b"!<arch>\n".to_vec();
+        for &(name, ref contents) in members {
+            let name = if name.starts_with('/') {
+                name.to_owned()
+            } else {
+                format!("{}/", name)
+            };
+            data.extend(
+                format!(
+                    "{:<16}{:<12}{:<6}{:<6}{:<8}{:<10}`\n",
+                    name,
+                    0,
+                    0,
+                    0,
+                    0,
+                    contents.len()
+                )
+                .as_bytes(),
+            );
+            data.extend(contents);
+            if contents.len() % 2 == 1 {
+                data.push(b'\n');
+            }
+        }
+        data
+    }
+
+    // A short import library member importing `symbol` from `dll`.
+    fn short_import(symbol: &str, dll: &str) -> Vec<u8> {
+        let names = format!("{}\0{}\0", symbol, dll);
+        let mut data = vec![0, 0, 0xff, 0xff, 0, 0, 0x64, 0x86, 0, 0, 0, 0];
+        data.extend(&[names.len() as u8, 0, 0, 0, 0, 0, 0, 0]);
+        data.extend(names.as_bytes());
+        data
+    }
+
+    // A minimal 64 bit PE image importing `imports` and delay loading `delay_imports`.
+    fn pe_file(imports: &[&str], delay_imports: &[&str]) -> Vec<u8> {
+        fn put_u32(data: &mut Vec<u8>, pos: usize, value: usize) {
+            for i in 0..4 {
+                data[pos + i] = (value >> (i * 8)) as u8;
+            }
+        }
+        const SECTION_RVA: usize = 0x1000;
+        const RAW_OFFSET: usize = 0x200;
+
+        // the section holds the import directory, the delay import directory and
+        // then the names
+        let delay_start = (imports.len() + 1) * 20;
+        let names_start = delay_start + (delay_imports.len() + 1) * 32;
+        let mut section = vec![0; names_start];
+        for (i, name) in imports.iter().chain(delay_imports).enumerate() {
+            let name_rva = SECTION_RVA + section.len();
+            let entry = if i < imports.len() {
+                i * 20 + 12
+            } else {
+                delay_start + (i - imports.len()) * 32 + 4
+            };
+            put_u32(&mut section, entry, name_rva);
+            section.extend(name.as_bytes());
+            section.push(0);
+        }
+
+        let mut data = vec![0; RAW_OFFSET];
+        data[0] = b'M';
+        data[1] = b'Z';
+        put_u32(&mut data, 0x3c, 0x40);
+        data[0x40..0x44].copy_from_slice(b"PE\0\0");
+        // COFF header: one section and a 240 byte optional header
+        data[0x46] = 1;
+        data[0x54] = 240;
+        // PE32+ optional header with the import and delay import directories
+        let optional_header = 0x58;
+        data[optional_header] = 0x0b;
+        data[optional_header + 1] = 0x02;
+        let directories = optional_header + 112;
+        put_u32(&mut data, directories + 8, SECTION_RVA);
+        put_u32(&mut data, directories + 12, delay_start);
+        if !delay_imports.is_empty() {
+            put_u32(&mut data, directories + 13 * 8, SECTION_RVA + delay_start);
+            put_u32(
+                &mut data,
+                directories + 13 * 8 + 4,
+                names_start - delay_start,
+            );
+        }
+        // section table
+        let header = optional_header + 240;
+        data[header..header + 6].copy_from_slice(b".idata");
+        put_u32(&mut data, header + 8, section.len());
+        put_u32(&mut data, header + 12, SECTION_RVA);
+        put_u32(&mut data, header + 16, section.len());
+        put_u32(&mut data, header + 20, RAW_OFFSET);
+
+        data.extend(section);
+        data
+    }
+
+    // A COFF object with a single section.
+    fn coff_object(section: &str, contents: &[u8]) -> Vec<u8> {
+        let mut data = vec![0x64, 0x86, 1, 0];
+        data.extend(&[0; 16]);
+        let mut name = section.as_bytes().to_vec();
+        name.resize(8, 0);
+        data.extend(&name);
+        data.extend(&[0; 8]);
+        data.extend(&[contents.len() as u8, 0, 0, 0, 60, 0, 0, 0]);
+        data.extend(&[0; 16]);
+        data.extend(contents);
+        data
     }
 
     // path to a to vcpkg installation to test against
@@ -1984,4 +7144,185 @@ mod tests {
         path.push(name);
         path
     }
+
+    // Just enough JSON to check the documents this crate writes.
+    #[derive(Clone, Debug, PartialEq)]
+    enum JsonValue {
+        Null,
+        Bool(bool),
+        Number(String),
+        String(String),
+        Array(Vec<JsonValue>),
+        Object(Vec<(String, JsonValue)>),
+    }
+
+    impl JsonValue {
+        fn get(&self, key: &str) -> Option<&JsonValue> {
+            match *self {
+                JsonValue::Object(ref members) => members
+                    .iter()
+                    .find(|&&(ref k, _)| k == key)
+                    .map(|&(_, ref v)| v),
+                _ => None,
+            }
+        }
+
+        fn as_str(&self) -> Option<&str> {
+            match *self {
+                JsonValue::String(ref s) => Some(s),
+                _ => None,
+            }
+        }
+    }
+
+    fn parse_json(text: &str) -> Option<JsonValue> {
+        let chars: Vec<char> = text.chars().collect();
+        let mut pos = 0;
+        let value = parse_json_value(&chars, &mut pos);
+        skip_json_whitespace(&chars, &mut pos);
+        if pos == chars.len() {
+            value
+        } else {
+            None
+        }
+    }
+
+    fn skip_json_whitespace(chars: &[char], pos: &mut usize) {
+        while *pos < chars.len() && chars[*pos].is_whitespace() {
+            *pos += 1;
+        }
+    }
+
+    fn parse_json_value(chars: &[char], pos: &mut usize) -> Option<JsonValue> {
+        skip_json_whitespace(chars, pos);
+        match chars.get(*pos) {
+            Some(&'{') => {
+                *pos += 1;
+                let mut members = Vec::new();
+                skip_json_whitespace(chars, pos);
+                if chars.get(*pos) == Some(&'}') {
+                    *pos += 1;
+                    return Some(JsonValue::Object(members));
+                }
+                loop {
+                    skip_json_whitespace(chars, pos);
+                    let key = match parse_json_value(chars, pos) {
+                        Some(JsonValue::String(key)) => key,
+                        _ => return None,
+                    };
+                    skip_json_whitespace(chars, pos);
+                    if chars.get(*pos) != Some(&':') {
+                        return None;
+                    }
+                    *pos += 1;
+                    match parse_json_value(chars, pos) {
+                        Some(value) => members.push((key, value)),
+                        None => return None,
+                    }
+                    skip_json_whitespace(chars, pos);
+                    match chars.get(*pos) {
+                        Some(&',') => *pos += 1,
+                        Some(&'}') => {
+                            *pos += 1;
+                            return Some(JsonValue::Object(members));
+                        }
+                        _ => return None,
+                    }
+                }
+            }
+            Some(&'[') => {
+                *pos += 1;
+                let mut items = Vec::new();
+                skip_json_whitespace(chars, pos);
+                if chars.get(*pos) == Some(&']') {
+                    *pos += 1;
+                    return Some(JsonValue::Array(items));
+                }
+                loop {
+                    match parse_json_value(chars, pos) {
+                        Some(item) => items.push(item),
+                        None => return None,
+                    }
+                    skip_json_whitespace(chars, pos);
+                    match chars.get(*pos) {
+                        Some(&',') => *pos += 1,
+                        Some(&']') => {
+                            *pos += 1;
+                            return Some(JsonValue::Array(items));
+                        }
+                        _ => return None,
+                    }
+                }
+            }
+            Some(&'"') => {
+                *pos += 1;
+                let mut s = String::new();
+                loop {
+                    match chars.get(*pos) {
+                        Some(&'"') => {
+                            *pos += 1;
+                            return Some(JsonValue::String(s));
+                        }
+                        Some(&'\\') => {
+                            *pos += 1;
+                            let c = match chars.get(*pos) {
+                                Some(&'n') => '\n',
+                                Some(&'t') => '\t',
+                                Some(&'r') => '\r',
+                                Some(&'b') => '\u{8}',
+                                Some(&'f') => '\u{c}',
+                                Some(&'u') => {
+                                    if *pos + 4 >= chars.len() {
+                                        return None;
+                                    }
+                                    let hex: String =
+                                        chars[*pos + 1..*pos + 5].iter().cloned().collect();
+                                    *pos += 4;
+                                    // surrogate pairs are not worth the trouble here
+                                    u32::from_str_radix(&hex, 16)
+                                        .ok()
+                                        .and_then(std::char::from_u32)
+                                        .unwrap_or('\u{fffd}')
+                                }
+                                Some(&c) => c,
+                                None => return None,
+                            };
+                            s.push(c);
+                            *pos += 1;
+                        }
+                        Some(&c) => {
+                            s.push(c);
+                            *pos += 1;
+                        }
+                        None => return None,
+                    }
+                }
+            }
+            Some(&c) if c == '-' || c.is_digit(10) => {
+                let start = *pos;
+                while *pos < chars.len()
+                    && (chars[*pos].is_digit(10) || "+-.eE".contains(chars[*pos]))
+                {
+                    *pos += 1;
+                }
+                Some(JsonValue::Number(
+                    chars[start..*pos].iter().cloned().collect(),
+                ))
+            }
+            _ => {
+                for &(word, ref value) in &[
+                    ("true", JsonValue::Bool(true)),
+                    ("false", JsonValue::Bool(false)),
+                    ("null", JsonValue::Null),
+                ] {
+                    let end = *pos + word.len();
+                    if end <= chars.len() && chars[*pos..end].iter().cloned().eq(word.chars()) {
+                        *pos = end;
+                        return Some(value.clone());
+                    }
+                }
+                None
+            }
+        }
+    }
 }
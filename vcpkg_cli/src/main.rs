@@ -3,6 +3,9 @@ extern crate vcpkg;
 
 use clap::{App, AppSettings, Arg, SubCommand};
 use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
 
 fn main() {
     let app = App::new("vcpkg library finder")
@@ -33,6 +36,69 @@ fn main() {
                         .takes_value(true)
                         .possible_values(&["dll", "static"]),
                 ),
+        )
+        .subcommand(
+            SubCommand::with_name("licenses")
+                .about("list the copyright files of a package and the packages it depends on")
+                .arg(
+                    Arg::with_name("package")
+                        .index(1)
+                        .required(true)
+                        .help("the package to collect copyright files for"),
+                )
+                .arg(
+                    Arg::with_name("copy-to")
+                        .long("copy-to")
+                        .value_name("DIR")
+                        .takes_value(true)
+                        .help("copy the copyright files and a licenses.txt manifest to DIR"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("sbom")
+                .about("print a CycloneDX SBOM of a package and the packages it depends on")
+                .arg(
+                    Arg::with_name("package")
+                        .index(1)
+                        .required(true)
+                        .help("the package to describe"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("deploy")
+                .about("copy the DLLs a package needs at runtime to a directory")
+                .arg(
+                    Arg::with_name("package")
+                        .index(1)
+                        .required(true)
+                        .help("the package to deploy"),
+                )
+                .arg(
+                    Arg::with_name("dir")
+                        .index(2)
+                        .required(true)
+                        .help("the directory to copy the DLLs to"),
+                )
+                .arg(
+                    Arg::with_name("pdbs")
+                        .long("pdbs")
+                        .help("copy the PDBs of the DLLs as well"),
+                )
+                .arg(
+                    Arg::with_name("manifest")
+                        .long("manifest")
+                        .help("write a vcpkg-deployment-<package>.json manifest to the directory"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("verify")
+                .about("check the installed files of a package and the packages it depends on")
+                .arg(
+                    Arg::with_name("package")
+                        .index(1)
+                        .required(true)
+                        .help("the package to check"),
+                ),
         );
 
     let matches = app.get_matches();
@@ -85,6 +151,13 @@ fn main() {
                     }
                 }
 
+                if !lib.rpaths.is_empty() {
+                    println!("Runtime search paths (rpath):");
+                    for line in &lib.rpaths {
+                        println!("  {}", line.display());
+                    }
+                }
+
                 if !lib.cargo_metadata.is_empty() {
                     println!("Cargo metadata:");
                     for line in &lib.cargo_metadata {
@@ -103,6 +176,24 @@ fn main() {
                         println!("  {}", line.display());
                     }
                 }
+                if !lib.unresolved_dlls.is_empty() {
+                    println!("Unresolved DLLs:");
+                    for line in &lib.unresolved_dlls {
+                        println!("  {}", line);
+                    }
+                }
+                if !lib.dynamic_ports.is_empty() {
+                    println!("Dynamically linked ports:");
+                    for line in &lib.dynamic_ports {
+                        println!("  {}", line);
+                    }
+                }
+                if !lib.tools.is_empty() {
+                    println!("Tools:");
+                    for (name, path) in &lib.tools {
+                        println!("  {} = {}", name, path.display());
+                    }
+                }
                 if !lib.found_names.is_empty() {
                     println!("Libraries linking names:");
                     for line in &lib.found_names {
@@ -112,6 +203,138 @@ fn main() {
             }
             Err(err) => {
                 println!("Failed:  {}", err);
+                process::exit(1);
+            }
+        }
+    }
+
+    if let Some(matches) = matches.subcommand_matches("licenses") {
+        let lib_name = matches.value_of("package").unwrap();
+
+        let mut cfg = vcpkg::Config::new();
+        cfg.cargo_metadata(false);
+        cfg.copy_dlls(false);
+
+        match cfg.find_package(lib_name) {
+            Ok(lib) => {
+                let copyright_files = lib.copyright_files();
+                for port in &lib.ports {
+                    match copyright_files.get(port) {
+                        Some(file) => println!("{}: {}", port, file.display()),
+                        None => println!("{}: no copyright file", port),
+                    }
+                }
+                if let Some(dir) = matches.value_of("copy-to") {
+                    match lib.copy_licenses(Path::new(dir)) {
+                        Ok(manifest) => println!("Wrote {}", manifest.display()),
+                        Err(err) => {
+                            println!("Failed:  {}", err);
+                            process::exit(1);
+                        }
+                    }
+                }
+            }
+            Err(err) => {
+                println!("Failed:  {}", err);
+                process::exit(1);
+            }
+        }
+    }
+
+    if let Some(matches) = matches.subcommand_matches("sbom") {
+        let lib_name = matches.value_of("package").unwrap();
+
+        let mut cfg = vcpkg::Config::new();
+        cfg.cargo_metadata(false);
+        cfg.copy_dlls(false);
+
+        match cfg.find_package(lib_name) {
+            Ok(lib) => print!("{}", lib.sbom_json()),
+            Err(err) => {
+                println!("Failed:  {}", err);
+                process::exit(1);
+            }
+        }
+    }
+
+    if let Some(matches) = matches.subcommand_matches("deploy") {
+        let lib_name = matches.value_of("package").unwrap();
+        let dir = Path::new(matches.value_of("dir").unwrap());
+
+        // dynamic linking is the only reason to deploy anything
+        env::set_var("VCPKGRS_DYNAMIC", "1");
+
+        let mut cfg = vcpkg::Config::new();
+        cfg.cargo_metadata(false);
+        cfg.copy_dlls(false);
+        cfg.copy_pdbs(matches.is_present("pdbs"));
+        if matches.is_present("manifest") {
+            // the manifest is written to OUT_DIR
+            if let Err(err) = fs::create_dir_all(dir) {
+                println!("Failed:  {}", err);
+                process::exit(1);
+            }
+            env::set_var("OUT_DIR", dir);
+            cfg.deployment_manifest(true);
+        }
+
+        let lib = match cfg.find_package(lib_name) {
+            Ok(lib) => lib,
+            Err(err) => {
+                println!("Failed:  {}", err);
+                process::exit(1);
+            }
+        };
+        for dll in &lib.unresolved_dlls {
+            println!("Warning: {} was not found", dll);
+        }
+        match lib.deploy(dir) {
+            Ok(files) => {
+                for file in &files {
+                    println!("Copied {}", file.display());
+                }
+            }
+            Err(err) => {
+                println!("Failed:  {}", err);
+                process::exit(1);
+            }
+        }
+        if matches.is_present("manifest") {
+            let path = dir.join(format!("vcpkg-deployment-{}.json", lib_name));
+            println!("Wrote {}", path.display());
+        }
+    }
+
+    if let Some(matches) = matches.subcommand_matches("verify") {
+        let lib_name = matches.value_of("package").unwrap();
+
+        let mut cfg = vcpkg::Config::new();
+        match cfg.verify(lib_name) {
+            Ok(file_problems) => {
+                let mut damaged = false;
+                for (port, problems) in &file_problems {
+                    if problems.is_empty() {
+                        println!("{}: ok", port);
+                    } else {
+                        // only missing files mean that the tree is damaged
+                        damaged = damaged
+                            || problems.iter().any(|problem| match *problem {
+                                vcpkg::FileProblem::Missing(_) => true,
+                                _ => false,
+                            });
+                        println!("{}: {} problems", port, problems.len());
+                        for problem in problems {
+                            println!("  {}", problem);
+                        }
+                    }
+                }
+                if damaged {
+                    process::exit(1);
+                }
+            }
+            Err(err) => {
+                println!("Failed:  {}", err);
+                process::exit(1);
             }
         }
     }